@@ -4,6 +4,8 @@ use semver::Version;
 fn main() {
     // Force rebuild whenever we change
     println!("cargo:rerun-if-changed=src/main.rs");
+    println!("cargo:rerun-if-changed=src/screens.rs");
+    println!("cargo:rerun-if-changed=src/window_handler.rs");
 
     let qt_version = std::env::var("DEP_QT_VERSION")
         .unwrap()
@@ -14,42 +14,95 @@ ApplicationWindow {
     height: windowProperties ? windowProperties.height : 600
     x: windowProperties ? windowProperties.x : 100
     y: windowProperties ? windowProperties.y : 100
-    visible: true
+
+    // Restores the window maximized or fullscreen if it was left that way. When started hidden, the
+    // window is only shown once something (IPC, the tray) asks for it
+    visibility: {
+        if (!windowProperties) {
+            return Window.AutomaticVisibility
+        }
+        return windowProperties.start_hidden ? Window.Hidden : windowProperties.visibility
+    }
+
+    // Whether we currently have a connection to the Pipeweaver daemon
+    property bool daemonConnected: true
+
+    // False while we're waiting for the daemon to start, the UI isn't loaded until this is set
+    property bool daemonReady: !(windowProperties && windowProperties.wait_for_daemon)
 
     // This is the IPC handler from rust
     Connections {
         target: windowHandler
         enabled: windowHandler != null
 
-        // Raise and Activate the window (on X11 this will bring to front, on Wayland it will highlight)
+        // Show, Raise and Activate the window (on Wayland this needs an activation token to take focus)
         function onTrigger() {
+            // The first time we're shown, this restores the saved window state
+            if (windowProperties && windowProperties.start_hidden) {
+                windowProperties.start_hidden = false
+            }
+            mainWindow.show()
             mainWindow.raise()
             mainWindow.requestActivate()
         }
 
-        // Close the Window
-        function onClose() {
-            mainWindow.close()
+        function onHide() {
+            mainWindow.hide()
         }
-    }
 
+        function onToggle() {
+            if (mainWindow.visible) {
+                mainWindow.hide()
+            } else {
+                onTrigger()
+            }
+        }
 
-    Timer {
-        id: windowHandlerPollTimer
-        interval: 20
-        repeat: true
-        running: true
-        onTriggered: {
-            // Make sure the IPC Handler is connected
-            if (!windowHandler) {
-                return
+        // Exit the app, making sure the geometry is saved even if the window is hidden
+        function onQuit() {
+            if (windowProperties) {
+                windowProperties.handle_quit_request()
+            }
+            Qt.quit()
+        }
+
+        function onReload() {
+            webView.reload()
+        }
+
+        // Paths are relative to the root of the Pipeweaver UI
+        function onNavigate(path) {
+            webView.url = webView.initialUrl + path
+        }
+
+        // Saved when the window is next closed, or the app exits
+        function onClose_to_tray(enabled) {
+            if (windowProperties) {
+                windowProperties.close_to_tray = enabled
             }
+        }
 
-            // Ask rust to check for message notifications
-            windowHandler.check_notifications()
+        // The daemon is up for the first time, load the UI
+        function onConnected() {
+            if (!mainWindow.daemonReady) {
+                mainWindow.daemonReady = true
+                webView.url = webView.initialUrl + webView.initialPath
+            }
+        }
+
+        // The daemon has gone away, show the overlay while Rust attempts to reconnect
+        function onDisconnected() {
+            mainWindow.daemonConnected = false
+        }
+
+        // The daemon is back, reload the UI so it picks up the new session
+        function onReconnected() {
+            mainWindow.daemonConnected = true
+            webView.reload()
         }
     }
 
+
     // Close request from Rust
     Connections {
         target: windowProperties
@@ -62,20 +115,25 @@ ApplicationWindow {
         interval: 250
         repeat: false
         onTriggered: {
-            if (windowProperties && mainWindow.visible) {
+            // Maximized and fullscreen sizes aren't worth keeping, we want the size to go back to
+            if (windowProperties && mainWindow.visibility === Window.Windowed) {
                 windowProperties.width = mainWindow.width
                 windowProperties.height = mainWindow.height
-                windowProperties.x = mainWindow.x
-                windowProperties.y = mainWindow.y
+
+                // On Wayland we're never told where the window really is
+                if (windowProperties.can_position) {
+                    windowProperties.x = mainWindow.x
+                    windowProperties.y = mainWindow.y
+                }
             }
         }
     }
 
     Timer {
         id: forceJavaScriptGC
-        interval: 10000
+        interval: windowProperties && windowProperties.gc_interval > 0 ? windowProperties.gc_interval : 10000
         repeat: true
-        running: true
+        running: !windowProperties || windowProperties.gc_interval > 0
         onTriggered: {
             webView.runJavaScript(`
                 if (window.gc) {
@@ -86,10 +144,35 @@ ApplicationWindow {
         }
     }
 
-    // When the Window is closed, throw back to the windowProperties to handle any final saving
-    onClosing: {
+    // When the Window is closed, throw back to the windowProperties to handle any final saving,
+    // if it wants to keep running in the tray, hide the window instead. Otherwise exit, even if
+    // the mixer is still open.
+    onClosing: (close) => {
+        if (windowProperties && !windowProperties.handle_close_request()) {
+            close.accepted = false
+            mainWindow.hide()
+        } else {
+            Qt.quit()
+        }
+    }
+    Component.onCompleted: {
+        if (windowHandler) {
+            windowHandler.set_visible(visible)
+        }
+    }
+    onVisibleChanged: {
         if (windowProperties) {
-            windowProperties.handle_close_request()
+            windowProperties.visible = visible
+        }
+        if (windowHandler) {
+            windowHandler.set_visible(visible)
+        }
+    }
+    onVisibilityChanged: {
+        // Hidden and minimized aren't states we want to come back in
+        if (windowProperties && (visibility === Window.Windowed
+                || visibility === Window.Maximized || visibility === Window.FullScreen)) {
+            windowProperties.visibility = visibility
         }
     }
     onWidthChanged: geometryChangeTimer.restart()
@@ -100,12 +183,59 @@ ApplicationWindow {
     WebEngineView {
         id: webView
         anchors.fill: parent
-        property string initialUrl: "http://localhost:14565"
+        property string initialUrl: windowProperties ? windowProperties.daemon_url : ""
+        property string initialPath: windowProperties ? windowProperties.initial_path : ""
 
         Component.onCompleted: {
-            url = initialUrl
+            if (mainWindow.daemonReady) {
+                url = initialUrl + initialPath
+            }
         }
 
         settings.pluginsEnabled: false
     }
+
+    // Displayed over the top of the WebView while we're waiting for the daemon to come back
+    Rectangle {
+        anchors.fill: parent
+        visible: !mainWindow.daemonConnected
+        color: "#cc000000"
+
+        // Swallow input so the stale UI can't be interacted with
+        MouseArea {
+            anchors.fill: parent
+            acceptedButtons: Qt.AllButtons
+        }
+
+        Label {
+            anchors.centerIn: parent
+            text: "Connection to Pipeweaver lost, reconnecting…"
+            color: "white"
+            font.pixelSize: 18
+        }
+    }
+
+    // Displayed while we wait for the daemon to start
+    Rectangle {
+        anchors.fill: parent
+        visible: !mainWindow.daemonReady
+        color: "#1e1e1e"
+
+        Column {
+            anchors.centerIn: parent
+            spacing: 16
+
+            BusyIndicator {
+                anchors.horizontalCenter: parent.horizontalCenter
+                running: parent.visible
+            }
+
+            Label {
+                anchors.horizontalCenter: parent.horizontalCenter
+                text: "Waiting for Pipeweaver…"
+                color: "white"
+                font.pixelSize: 18
+            }
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,163 @@
+import QtQuick
+import QtQuick.Controls
+import QtQuick.Layouts
+
+// A lightweight mixer, driven entirely by the daemon state mirrored in Rust (no WebEngine)
+Window {
+    id: mixerWindow
+    title: "Pipeweaver Mixer"
+
+    minimumWidth: 240
+    minimumHeight: 160
+
+    // These all come from Rust via the MixerProperties QObject
+    width: mixerProperties ? mixerProperties.width : 360
+    height: mixerProperties ? mixerProperties.height : 480
+    x: mixerProperties ? mixerProperties.x : 100
+    y: mixerProperties ? mixerProperties.y : 100
+    visible: mixerProperties ? mixerProperties.visible : false
+
+    flags: Qt.Window | (mixerProperties && mixerProperties.always_on_top ? Qt.WindowStaysOnTopHint : 0)
+    color: "#1e1e1e"
+
+    Connections {
+        target: windowHandler
+        enabled: windowHandler != null
+
+        function onShow_mixer() {
+            mixerWindow.show()
+            mixerWindow.raise()
+            mixerWindow.requestActivate()
+        }
+    }
+
+    Connections {
+        target: daemonState
+        enabled: daemonState != null
+
+        function onState_changed() {
+            mixerWindow.sync()
+        }
+    }
+
+    // Update the channels in place where we can, so sliders aren't recreated while being dragged
+    ListModel {
+        id: channelModel
+    }
+
+    function sync() {
+        const channels = daemonState ? daemonState.channels : []
+        let sameChannels = channels.length === channelModel.count
+        for (let i = 0; sameChannels && i < channels.length; i++) {
+            sameChannels = channelModel.get(i).channelId === channels[i].id
+        }
+
+        if (!sameChannels) {
+            channelModel.clear()
+            for (const channel of channels) {
+                channelModel.append({
+                    channelId: channel.id,
+                    name: channel.name,
+                    volume: channel.volume,
+                    muted: channel.muted
+                })
+            }
+            return
+        }
+
+        for (let i = 0; i < channels.length; i++) {
+            channelModel.setProperty(i, "name", channels[i].name)
+            channelModel.setProperty(i, "volume", channels[i].volume)
+            channelModel.setProperty(i, "muted", channels[i].muted)
+        }
+    }
+
+    // As with the main window, geometry changes are sent back to Rust to be saved on exit
+    Timer {
+        id: geometryChangeTimer
+        interval: 250
+        repeat: false
+        onTriggered: {
+            if (mixerProperties && mixerWindow.visibility === Window.Windowed) {
+                mixerProperties.width = mixerWindow.width
+                mixerProperties.height = mixerWindow.height
+
+                // On Wayland we're never told where the window really is
+                if (mixerProperties.can_position) {
+                    mixerProperties.x = mixerWindow.x
+                    mixerProperties.y = mixerWindow.y
+                }
+            }
+        }
+    }
+
+    onWidthChanged: geometryChangeTimer.restart()
+    onHeightChanged: geometryChangeTimer.restart()
+    onXChanged: geometryChangeTimer.restart()
+    onYChanged: geometryChangeTimer.restart()
+
+    onVisibleChanged: {
+        if (mixerProperties) {
+            mixerProperties.visible = visible
+        }
+    }
+
+    // Hide rather than close, closing the last visible window would exit the app
+    onClosing: (close) => {
+        close.accepted = false
+        mixerWindow.hide()
+    }
+
+    Component.onCompleted: sync()
+
+    ListView {
+        anchors.fill: parent
+        anchors.margins: 12
+        spacing: 10
+        clip: true
+        model: channelModel
+
+        delegate: RowLayout {
+            width: ListView.view.width
+            spacing: 8
+
+            Label {
+                Layout.preferredWidth: 96
+                text: model.name
+                color: "white"
+                elide: Text.ElideRight
+            }
+
+            Slider {
+                id: slider
+                Layout.fillWidth: true
+                from: 0
+                to: 100
+                stepSize: 1
+                opacity: model.muted ? 0.4 : 1.0
+
+                // Don't fight the user while they're dragging
+                Binding on value {
+                    when: !slider.pressed
+                    value: model.volume
+                }
+
+                onMoved: mixerProperties.set_volume(model.channelId, Math.round(value))
+            }
+
+            Button {
+                text: model.muted ? "Unmute" : "Mute"
+                checkable: false
+                highlighted: model.muted
+                onClicked: mixerProperties.set_muted(model.channelId, !model.muted)
+            }
+        }
+    }
+
+    Label {
+        anchors.centerIn: parent
+        visible: channelModel.count === 0
+        text: "Waiting for Pipeweaver…"
+        color: "white"
+    }
+}
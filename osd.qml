@@ -0,0 +1,114 @@
+import QtQuick
+import QtQuick.Controls
+
+// A small transient window showing volume and mute changes, independent of the main window
+Window {
+    id: osdWindow
+
+    flags: Qt.Tool | Qt.FramelessWindowHint | Qt.WindowStaysOnTopHint | Qt.WindowDoesNotAcceptFocus
+        | Qt.WindowTransparentForInput
+    color: "transparent"
+    visible: false
+
+    width: 320
+    height: 72
+
+    property string channelName: ""
+    property int level: 0
+    property bool muted: false
+
+    Connections {
+        target: daemonState
+        enabled: daemonState != null && osdProperties != null
+
+        function onChannel_changed(id, name, volume, muted) {
+            if (!osdProperties.shows_channel(id, name)) {
+                return
+            }
+
+            osdWindow.channelName = name
+            osdWindow.level = volume
+            osdWindow.muted = muted
+
+            osdWindow.reposition()
+            osdWindow.visible = true
+            hideTimer.restart()
+        }
+    }
+
+    Timer {
+        id: hideTimer
+        interval: osdProperties ? osdProperties.timeout : 1500
+        repeat: false
+        onTriggered: osdWindow.visible = false
+    }
+
+    // Places the window on its screen, compositors which don't allow this (Wayland) will ignore it
+    function reposition() {
+        const margin = 48
+        const position = osdProperties ? osdProperties.position : "bottom"
+        // QML only gives us the available area of the whole desktop, so use this screen's full
+        // size, the margin keeps us clear of most panels
+        const area = {
+            x: osdWindow.screen.virtualX,
+            y: osdWindow.screen.virtualY,
+            width: osdWindow.screen.width,
+            height: osdWindow.screen.height
+        }
+
+        if (position.endsWith("left")) {
+            osdWindow.x = area.x + margin
+        } else if (position.endsWith("right")) {
+            osdWindow.x = area.x + area.width - osdWindow.width - margin
+        } else {
+            osdWindow.x = area.x + (area.width - osdWindow.width) / 2
+        }
+
+        if (position.startsWith("top")) {
+            osdWindow.y = area.y + margin
+        } else if (position.startsWith("bottom")) {
+            osdWindow.y = area.y + area.height - osdWindow.height - margin
+        } else {
+            osdWindow.y = area.y + (area.height - osdWindow.height) / 2
+        }
+    }
+
+    Rectangle {
+        anchors.fill: parent
+        radius: 12
+        color: "#e61e1e1e"
+
+        Column {
+            anchors.fill: parent
+            anchors.margins: 14
+            spacing: 8
+
+            Row {
+                width: parent.width
+
+                Label {
+                    width: parent.width - levelLabel.width
+                    text: osdWindow.channelName
+                    color: "white"
+                    elide: Text.ElideRight
+                    font.pixelSize: 15
+                }
+
+                Label {
+                    id: levelLabel
+                    text: osdWindow.muted ? "Muted" : osdWindow.level + "%"
+                    color: osdWindow.muted ? "#ff6b6b" : "white"
+                    font.pixelSize: 15
+                }
+            }
+
+            ProgressBar {
+                width: parent.width
+                from: 0
+                to: 100
+                value: osdWindow.level
+                opacity: osdWindow.muted ? 0.4 : 1.0
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use anyhow::{Context, Result};
+use log::{debug, info};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const ENTRY_NAME: &str = "pipeweaver-app.desktop";
+
+/// The XDG autostart entry for the app, as read by the desktop session at login
+fn get_entry_path() -> PathBuf {
+    let mut path = dirs::config_dir().unwrap_or_else(|| PathBuf::from("."));
+    path.push("autostart");
+    path.push(ENTRY_NAME);
+    path
+}
+
+pub fn is_enabled() -> bool {
+    fs::read_to_string(get_entry_path()).is_ok_and(|entry| entry_enabled(&entry))
+}
+
+// The desktop's own settings (or the user) can turn the entry off without removing it
+fn entry_enabled(entry: &str) -> bool {
+    let mut in_entry = false;
+    for line in entry.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+            continue;
+        }
+
+        if !in_entry {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        match (key.trim(), value.trim()) {
+            ("Hidden", "true") | ("X-GNOME-Autostart-enabled", "false") => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
+pub fn set_enabled(enabled: bool) -> Result<()> {
+    let path = get_entry_path();
+    if !enabled {
+        if path.exists() {
+            fs::remove_file(&path).with_context(|| format!("Unable to remove {path:?}"))?;
+            info!("Removed autostart entry {path:?}");
+        }
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Unable to create {parent:?}"))?;
+    }
+    fs::write(&path, desktop_entry()).with_context(|| format!("Unable to write {path:?}"))?;
+    info!("Created autostart entry {path:?}");
+    Ok(())
+}
+
+fn desktop_entry() -> String {
+    // Point at wherever we're running from, so this works outside of /usr/bin
+    let exec = env::current_exe()
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| crate::APP_NAME.to_string());
+    debug!("Autostart will launch {exec}");
+
+    format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=Pipeweaver App\n\
+         Comment=A Frontend App for Pipeweaver\n\
+         Exec={} --hidden\n\
+         Icon=pipeweaver\n\
+         Terminal=false\n\
+         X-GNOME-Autostart-enabled=true\n",
+        quote_exec(&exec)
+    )
+}
+
+// Exec arguments containing reserved characters need quoting, see the Desktop Entry spec
+fn quote_exec(arg: &str) -> String {
+    // Field codes start with %, so a literal one needs doubling
+    let arg = arg.replace('%', "%%");
+    if !arg.contains(|c: char| " \t\n\"'\\><~|&;$*?#()`".contains(c)) {
+        return arg;
+    }
+
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        if matches!(c, '"' | '`' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+
+    // Inside a .desktop value, backslashes themselves need escaping again
+    quoted.replace('\\', "\\\\")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_enabled_reads_the_disable_keys() {
+        let entry = "[Desktop Entry]\nType=Application\nExec=pipeweaver-app --hidden\n";
+        assert!(entry_enabled(entry));
+        assert!(entry_enabled(&desktop_entry()));
+
+        assert!(!entry_enabled(&format!("{entry}Hidden=true\n")));
+        assert!(!entry_enabled(&format!(
+            "{entry}X-GNOME-Autostart-enabled = false\n"
+        )));
+        assert!(entry_enabled(&format!("{entry}Hidden=false\n")));
+    }
+
+    #[test]
+    fn entry_enabled_ignores_other_groups() {
+        let entry = "[Desktop Entry]\nType=Application\n\n[Desktop Action hide]\nHidden=true\n";
+        assert!(entry_enabled(entry));
+    }
+
+    #[test]
+    fn quote_exec_leaves_plain_paths() {
+        assert_eq!(
+            quote_exec("/usr/bin/pipeweaver-app"),
+            "/usr/bin/pipeweaver-app"
+        );
+    }
+
+    #[test]
+    fn quote_exec_doubles_percent() {
+        assert_eq!(quote_exec("/opt/100%/app"), "/opt/100%%/app");
+        assert_eq!(quote_exec("/opt/100% sure/app"), "\"/opt/100%% sure/app\"");
+    }
+
+    #[test]
+    fn quote_exec_quotes_spaces() {
+        assert_eq!(quote_exec("/opt/my apps/app"), "\"/opt/my apps/app\"");
+    }
+
+    // Escaped once for the Exec quoting rules, and then again as a .desktop string value
+    #[test]
+    fn quote_exec_escapes_twice() {
+        assert_eq!(quote_exec(r#"/opt/a"b/app"#), r#""/opt/a\\"b/app""#);
+        assert_eq!(quote_exec(r"/opt/a\b/app"), r#""/opt/a\\\\b/app""#);
+        assert_eq!(quote_exec("/opt/$HOME/app"), r#""/opt/\\$HOME/app""#);
+    }
+}
@@ -0,0 +1,771 @@
+use crate::websocket::{ReconnectPolicy, WaitPolicy};
+use anyhow::{Context, Result, bail};
+use clap::{ArgAction, Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, fs};
+use tungstenite::http::Uri;
+
+// Overrides the location of the config file
+const CONFIG_ENV: &str = "PIPEWEAVER_APP_CONFIG";
+
+// Overrides the daemon address, in the form http://host:port/path
+const URL_ENV: &str = "PIPEWEAVER_APP_URL";
+
+// How long (in seconds) to keep trying to reconnect to the daemon before closing
+const GIVE_UP_ENV: &str = "PIPEWEAVER_APP_GIVE_UP";
+
+// The usual env_logger filter
+const LOG_ENV: &str = "RUST_LOG";
+
+// Read by QtWebEngine itself, so we only set it if the user hasn't
+pub const CHROMIUM_FLAGS_ENV: &str = "QTWEBENGINE_CHROMIUM_FLAGS";
+
+const DEFAULT_HOST: &str = "localhost";
+const DEFAULT_PORT: u16 = 14565;
+
+const DEFAULT_WAIT_TIMEOUT: u64 = 60;
+const DEFAULT_WAIT_INTERVAL: u64 = 1000;
+
+const DEFAULT_UNIT: &str = "pipeweaver.service";
+const DEFAULT_BINARY: &str = "pipeweaver";
+const DEFAULT_LAUNCH_TIMEOUT: u64 = 30;
+
+const DEFAULT_OSD_TIMEOUT: u64 = 1500;
+
+const DEFAULT_LOG_LEVEL: &str = "info";
+
+// Keeps the memory use of the web UI down, the GC timer relies on --expose-gc
+const DEFAULT_CHROMIUM_FLAGS: &str = "--enable-features=Canvas2DImageChromium \
+     --enable-gpu-memory-buffer-compositor-resources \
+     --enable-zero-copy \
+     --force-gpu-mem-available-mb=256 \
+     --max-decoded-image-size-mb=64 \
+     --js-flags=--expose-gc,--max-old-space-size=128 \
+     --disable-software-rasterizer \
+     --disable-dev-shm-usage \
+     --disable-gpu-shader-disk-cache \
+     --num-raster-threads=2 \
+     --single-process";
+const DEFAULT_GC_INTERVAL: u64 = 10000;
+
+const PRECEDENCE_HELP: &str = "\
+Each setting is taken from the first of these that sets it:
+  1. The command line
+  2. The environment (PIPEWEAVER_APP_URL, PIPEWEAVER_APP_GIVE_UP, RUST_LOG,
+     QTWEBENGINE_CHROMIUM_FLAGS)
+  3. The config file (--config, PIPEWEAVER_APP_CONFIG, or ~/.config/pipeweaver/app.toml)
+  4. The built-in defaults
+
+Use --print-config to see the result.";
+
+#[derive(Parser, Debug)]
+#[command(version, about, after_help = PRECEDENCE_HELP)]
+pub struct Cli {
+    /// Read the config from this file, rather than ~/.config/pipeweaver/app.toml
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// The address of the Pipeweaver daemon, in the form http://host:port/path
+    #[arg(long, conflicts_with_all = ["host", "port", "path"])]
+    pub url: Option<String>,
+
+    /// The host the Pipeweaver daemon is listening on
+    #[arg(long)]
+    pub host: Option<String>,
+
+    /// The port the Pipeweaver daemon is listening on
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// The base path of the Pipeweaver UI (if served behind a proxy)
+    #[arg(long)]
+    pub path: Option<String>,
+
+    /// Open the window immediately and wait for Pipeweaver to start, rather than failing
+    #[arg(long)]
+    pub wait: bool,
+
+    /// How long (in seconds) to wait for Pipeweaver before giving up, implies --wait
+    #[arg(long, value_name = "SECONDS")]
+    pub wait_timeout: Option<u64>,
+
+    /// Start Pipeweaver automatically if it's not running, rather than asking
+    #[arg(long)]
+    pub start_daemon: bool,
+
+    /// Open the UI at the given page (for example 'mixer')
+    #[arg(long)]
+    pub page: Option<String>,
+
+    /// If the app is already running, show or hide its window
+    #[arg(long)]
+    pub toggle: bool,
+
+    /// Start in the background without showing the window (for example, when autostarted)
+    #[arg(long)]
+    pub hidden: bool,
+
+    /// Open the mini mixer window
+    #[arg(long)]
+    pub mixer: bool,
+
+    /// Enable or disable launching the app at login, then exit
+    #[arg(long, value_name = "ACTION")]
+    pub autostart: Option<AutostartAction>,
+
+    /// Log in more detail, repeat for even more (-vv)
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Forget where the window and mini mixer were, and start them at their default size
+    #[arg(long)]
+    pub reset_geometry: bool,
+
+    /// Print the configuration, with the environment and command line applied, then exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AutostartAction {
+    Enable,
+    Disable,
+}
+
+impl Cli {
+    /// The requested page as a path relative to the root of the UI
+    pub fn page_path(&self) -> Option<String> {
+        self.page
+            .as_ref()
+            .map(|page| format!("/{}", page.trim_start_matches('/')))
+    }
+}
+
+// The on-disk layout of the config file, everything is optional
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    daemon: DaemonSection,
+    startup: StartupSection,
+    launch: LaunchSection,
+    reconnect: ReconnectSection,
+    tray: TraySection,
+    logging: LoggingSection,
+    webengine: WebEngineSection,
+    shortcuts: Vec<Shortcut>,
+    notifications: NotificationConfig,
+    osd: OsdConfig,
+    mixer: MixerConfig,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct DaemonSection {
+    host: Option<String>,
+    port: Option<u16>,
+    path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct StartupSection {
+    wait_for_daemon: bool,
+    wait_timeout: Option<u64>,
+    wait_interval_ms: Option<u64>,
+    start_hidden: bool,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct LaunchSection {
+    automatic: bool,
+    unit: Option<String>,
+    binary: Option<String>,
+    timeout: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ReconnectSection {
+    initial_delay_ms: Option<u64>,
+    max_delay_ms: Option<u64>,
+
+    // In seconds, unset to keep trying forever
+    give_up_after: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TraySection {
+    enabled: bool,
+}
+
+impl Default for TraySection {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct LoggingSection {
+    // An env_logger filter, such as 'debug' or 'pipeweaver_app=trace'
+    level: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct WebEngineSection {
+    chromium_flags: Option<String>,
+
+    // How often the web UI is asked to collect garbage, 0 to never
+    gc_interval_ms: Option<u64>,
+}
+
+/// Which events we show desktop notifications for, everything is off unless enabled
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationConfig {
+    // The connection to the daemon being lost and restored
+    pub connection: bool,
+
+    // Physical devices appearing or disappearing
+    pub devices: bool,
+
+    // The active profile changing
+    pub profile: bool,
+}
+
+impl NotificationConfig {
+    pub fn any(&self) -> bool {
+        self.connection || self.devices || self.profile
+    }
+}
+
+/// The on-screen display shown when a channel's volume or mute state changes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct OsdConfig {
+    pub enabled: bool,
+    pub position: OsdPosition,
+
+    // How long the OSD stays on screen after the last change
+    pub timeout_ms: u64,
+
+    // Channel names or IDs to show the OSD for, empty for all of them
+    pub channels: Vec<String>,
+}
+
+impl Default for OsdConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            position: OsdPosition::default(),
+            timeout_ms: DEFAULT_OSD_TIMEOUT,
+            channels: vec![],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum OsdPosition {
+    TopLeft,
+    Top,
+    TopRight,
+    Center,
+    BottomLeft,
+    #[default]
+    Bottom,
+    BottomRight,
+}
+
+impl OsdPosition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OsdPosition::TopLeft => "top-left",
+            OsdPosition::Top => "top",
+            OsdPosition::TopRight => "top-right",
+            OsdPosition::Center => "center",
+            OsdPosition::BottomLeft => "bottom-left",
+            OsdPosition::Bottom => "bottom",
+            OsdPosition::BottomRight => "bottom-right",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct MixerConfig {
+    // Keep the mini mixer above other windows
+    pub always_on_top: bool,
+}
+
+impl Default for MixerConfig {
+    fn default() -> Self {
+        Self {
+            always_on_top: true,
+        }
+    }
+}
+
+/// A global shortcut, registered with the desktop through the GlobalShortcuts portal
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Shortcut {
+    pub id: String,
+    pub description: Option<String>,
+
+    // A hint for the desktop (for example 'CTRL+ALT+P'), the user has the final say
+    pub trigger: Option<String>,
+    pub action: ShortcutAction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum ShortcutAction {
+    Show,
+    Hide,
+    Toggle,
+
+    // A raw request, sent as-is to the daemon over the websocket
+    Daemon(serde_json::Value),
+}
+
+pub struct Config {
+    // The config file we read, if there was one
+    pub file: Option<PathBuf>,
+
+    pub daemon: DaemonAddress,
+
+    // If set, we'll wait for the daemon to appear rather than failing to start
+    pub wait: Option<WaitPolicy>,
+
+    pub launch: LaunchConfig,
+    pub reconnect: ReconnectPolicy,
+
+    // Whether to show an icon in the system tray
+    pub tray: bool,
+
+    // Don't show the window until it's requested
+    pub start_hidden: bool,
+
+    pub shortcuts: Vec<Shortcut>,
+    pub notifications: NotificationConfig,
+    pub osd: OsdConfig,
+    pub mixer: MixerConfig,
+
+    // An env_logger filter, such as 'info' or 'pipeweaver_app=debug'
+    pub log_level: String,
+    pub webengine: WebEngineConfig,
+}
+
+pub struct WebEngineConfig {
+    pub chromium_flags: String,
+
+    // How often the web UI is asked to collect garbage, 0 to never
+    pub gc_interval_ms: u64,
+}
+
+/// How we go about starting the daemon if it's not running
+pub struct LaunchConfig {
+    // Start the daemon without asking the user first
+    pub automatic: bool,
+
+    // The systemd user unit to start, and the binary to spawn if that fails
+    pub unit: String,
+    pub binary: String,
+
+    // How long we wait for the daemon to become available once started
+    pub wait: WaitPolicy,
+}
+
+impl Config {
+    /// Resolves the configuration, with the CLI taking priority over the environment, which in
+    /// turn takes priority over the config file.
+    pub fn load(cli: &Cli) -> Result<Self> {
+        let (path, required) = match (&cli.config, env::var_os(CONFIG_ENV)) {
+            (Some(path), _) => (path.clone(), true),
+            (None, Some(path)) => (PathBuf::from(path), true),
+            (None, None) => (Self::get_config_path(), false),
+        };
+        let (file, path) = match Self::load_file(&path, required)? {
+            Some(file) => (file, Some(path)),
+            None => (ConfigFile::default(), None),
+        };
+
+        Self::resolve(cli, file, path, |name| env::var(name).ok())
+    }
+
+    // Applies the precedence rules, with the environment passed in so they can be tested
+    fn resolve(
+        cli: &Cli,
+        file: ConfigFile,
+        path: Option<PathBuf>,
+        get_env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self> {
+        let mut daemon = DaemonAddress {
+            host: file.daemon.host.unwrap_or_else(|| DEFAULT_HOST.to_string()),
+            port: file.daemon.port.unwrap_or(DEFAULT_PORT),
+            path: file.daemon.path.unwrap_or_default(),
+        };
+
+        if let Some(url) = get_env(URL_ENV) {
+            daemon = DaemonAddress::from_url(&url).with_context(|| format!("Invalid {URL_ENV}"))?;
+        }
+
+        if let Some(url) = &cli.url {
+            daemon = DaemonAddress::from_url(url).context("Invalid --url")?;
+        }
+        if let Some(host) = &cli.host {
+            daemon.host = host.clone();
+        }
+        if let Some(port) = cli.port {
+            daemon.port = port;
+        }
+        if let Some(path) = &cli.path {
+            daemon.path = path.clone();
+        }
+        daemon.path = normalise_path(&daemon.path);
+
+        let wait_interval = Duration::from_millis(
+            file.startup
+                .wait_interval_ms
+                .unwrap_or(DEFAULT_WAIT_INTERVAL),
+        );
+        let wait_timeout = cli.wait_timeout.or(file.startup.wait_timeout);
+        let wait = if cli.wait || cli.wait_timeout.is_some() || file.startup.wait_for_daemon {
+            Some(WaitPolicy {
+                interval: wait_interval,
+                timeout: Duration::from_secs(wait_timeout.unwrap_or(DEFAULT_WAIT_TIMEOUT)),
+            })
+        } else {
+            None
+        };
+
+        let launch = LaunchConfig {
+            automatic: cli.start_daemon || file.launch.automatic,
+            unit: file.launch.unit.unwrap_or_else(|| DEFAULT_UNIT.to_string()),
+            binary: file
+                .launch
+                .binary
+                .unwrap_or_else(|| DEFAULT_BINARY.to_string()),
+            wait: WaitPolicy {
+                interval: wait_interval,
+                timeout: Duration::from_secs(file.launch.timeout.unwrap_or(DEFAULT_LAUNCH_TIMEOUT)),
+            },
+        };
+
+        let mut reconnect = ReconnectPolicy::default();
+        if let Some(delay) = file.reconnect.initial_delay_ms {
+            reconnect.initial_delay = Duration::from_millis(delay);
+        }
+        if let Some(delay) = file.reconnect.max_delay_ms {
+            reconnect.max_delay = Duration::from_millis(delay);
+        }
+        let give_up_after = match get_env(GIVE_UP_ENV) {
+            Some(value) => Some(
+                value
+                    .parse::<u64>()
+                    .with_context(|| format!("Invalid {GIVE_UP_ENV} value '{value}'"))?,
+            ),
+            None => file.reconnect.give_up_after,
+        };
+        reconnect.give_up_after = give_up_after.map(Duration::from_secs);
+
+        let log_level = match cli.verbose {
+            0 => get_env(LOG_ENV)
+                .filter(|level| !level.is_empty())
+                .or(file.logging.level)
+                .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string()),
+            1 => "debug".to_string(),
+            _ => "trace".to_string(),
+        };
+
+        let webengine = WebEngineConfig {
+            chromium_flags: get_env(CHROMIUM_FLAGS_ENV)
+                .or(file.webengine.chromium_flags)
+                .unwrap_or_else(|| DEFAULT_CHROMIUM_FLAGS.to_string()),
+            gc_interval_ms: file.webengine.gc_interval_ms.unwrap_or(DEFAULT_GC_INTERVAL),
+        };
+
+        Ok(Self {
+            file: path,
+            daemon,
+            wait,
+            launch,
+            reconnect,
+            tray: file.tray.enabled,
+            start_hidden: cli.hidden || file.startup.start_hidden,
+            shortcuts: file.shortcuts,
+            notifications: file.notifications,
+            osd: file.osd,
+            mixer: file.mixer,
+            log_level,
+            webengine,
+        })
+    }
+
+    pub fn get_config_path() -> PathBuf {
+        let mut path = dirs::config_dir().unwrap_or_else(|| PathBuf::from("."));
+        path.push("pipeweaver");
+        path.push("app.toml");
+        path
+    }
+
+    // The default file is optional, but one we've been pointed at must exist
+    fn load_file(path: &Path, required: bool) -> Result<Option<ConfigFile>> {
+        if !required && !path.exists() {
+            return Ok(None);
+        }
+
+        let content =
+            fs::read_to_string(path).with_context(|| format!("Unable to read {path:?}"))?;
+        let file = toml::from_str(&content).with_context(|| format!("Unable to parse {path:?}"))?;
+        Ok(Some(file))
+    }
+
+    /// The resolved configuration, in the same form as the config file
+    pub fn to_toml(&self) -> Result<String> {
+        let file = ConfigFile {
+            daemon: DaemonSection {
+                host: Some(self.daemon.host.clone()),
+                port: Some(self.daemon.port),
+                path: Some(self.daemon.path.clone()),
+            },
+            startup: StartupSection {
+                wait_for_daemon: self.wait.is_some(),
+                wait_timeout: self.wait.map(|wait| wait.timeout.as_secs()),
+                wait_interval_ms: Some(self.launch.wait.interval.as_millis() as u64),
+                start_hidden: self.start_hidden,
+            },
+            launch: LaunchSection {
+                automatic: self.launch.automatic,
+                unit: Some(self.launch.unit.clone()),
+                binary: Some(self.launch.binary.clone()),
+                timeout: Some(self.launch.wait.timeout.as_secs()),
+            },
+            reconnect: ReconnectSection {
+                initial_delay_ms: Some(self.reconnect.initial_delay.as_millis() as u64),
+                max_delay_ms: Some(self.reconnect.max_delay.as_millis() as u64),
+                give_up_after: self.reconnect.give_up_after.map(|after| after.as_secs()),
+            },
+            tray: TraySection { enabled: self.tray },
+            logging: LoggingSection {
+                level: Some(self.log_level.clone()),
+            },
+            webengine: WebEngineSection {
+                chromium_flags: Some(self.webengine.chromium_flags.clone()),
+                gc_interval_ms: Some(self.webengine.gc_interval_ms),
+            },
+            shortcuts: self.shortcuts.clone(),
+            notifications: self.notifications,
+            osd: self.osd.clone(),
+            mixer: self.mixer,
+        };
+        Ok(toml::to_string(&file)?)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DaemonAddress {
+    pub host: String,
+    pub port: u16,
+
+    // Either empty, or a path starting with '/' with no trailing slash
+    pub path: String,
+}
+
+impl DaemonAddress {
+    fn from_url(url: &str) -> Result<Self> {
+        let uri = url.parse::<Uri>()?;
+        if uri.scheme_str() != Some("http") {
+            bail!("Only http:// URLs are supported");
+        }
+
+        let Some(host) = uri.host() else {
+            bail!("URL has no host");
+        };
+
+        Ok(Self {
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port: uri.port_u16().unwrap_or(80),
+            path: uri.path().to_string(),
+        })
+    }
+
+    pub fn authority(&self) -> String {
+        if self.host.contains(':') {
+            // IPv6 addresses need to be bracketed
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    /// The URL of the web UI, as loaded by the WebEngineView
+    pub fn base_url(&self) -> String {
+        format!("http://{}{}", self.authority(), self.path)
+    }
+
+    pub fn websocket_uri(&self) -> Result<Uri> {
+        Ok(Uri::builder()
+            .scheme("ws")
+            .authority(self.authority())
+            .path_and_query(format!("{}/api/websocket", self.path))
+            .build()?)
+    }
+}
+
+fn normalise_path(path: &str) -> String {
+    let trimmed = path.trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{trimmed}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn cli(args: &[&str]) -> Cli {
+        Cli::try_parse_from(std::iter::once("pipeweaver-app").chain(args.iter().copied())).unwrap()
+    }
+
+    fn file(toml: &str) -> ConfigFile {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn resolve(args: &[&str], toml: &str, env: &[(&str, &str)]) -> Result<Config> {
+        let env: HashMap<_, _> = env.iter().copied().collect();
+        Config::resolve(&cli(args), file(toml), None, |name| {
+            env.get(name).map(|value| value.to_string())
+        })
+    }
+
+    const FILE: &str = r#"
+        [daemon]
+        host = "filehost"
+        port = 1234
+        path = "/file"
+
+        [reconnect]
+        give_up_after = 30
+
+        [logging]
+        level = "warn"
+
+        [webengine]
+        chromium_flags = "--file-flag"
+    "#;
+
+    const ENV: &[(&str, &str)] = &[
+        (URL_ENV, "http://envhost:99/env/"),
+        (GIVE_UP_ENV, "5"),
+        (LOG_ENV, "error"),
+        (CHROMIUM_FLAGS_ENV, "--env-flag"),
+    ];
+
+    #[test]
+    fn defaults() {
+        let config = resolve(&[], "", &[]).unwrap();
+        assert_eq!(config.daemon.base_url(), "http://localhost:14565");
+        assert_eq!(config.reconnect.give_up_after, None);
+        assert_eq!(config.log_level, DEFAULT_LOG_LEVEL);
+        assert_eq!(config.webengine.chromium_flags, DEFAULT_CHROMIUM_FLAGS);
+        assert_eq!(config.webengine.gc_interval_ms, DEFAULT_GC_INTERVAL);
+        assert!(config.wait.is_none());
+    }
+
+    #[test]
+    fn file_overrides_defaults() {
+        let config = resolve(&[], FILE, &[]).unwrap();
+        assert_eq!(config.daemon.base_url(), "http://filehost:1234/file");
+        assert_eq!(
+            config.reconnect.give_up_after,
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(config.log_level, "warn");
+        assert_eq!(config.webengine.chromium_flags, "--file-flag");
+    }
+
+    #[test]
+    fn environment_overrides_file() {
+        let config = resolve(&[], FILE, ENV).unwrap();
+        assert_eq!(config.daemon.base_url(), "http://envhost:99/env");
+        assert_eq!(config.reconnect.give_up_after, Some(Duration::from_secs(5)));
+        assert_eq!(config.log_level, "error");
+        assert_eq!(config.webengine.chromium_flags, "--env-flag");
+    }
+
+    #[test]
+    fn cli_overrides_environment() {
+        let config = resolve(&["--url", "http://clihost:7/"], FILE, ENV).unwrap();
+        assert_eq!(config.daemon.base_url(), "http://clihost:7");
+
+        // Individual parts replace just that part of the address
+        let config = resolve(&["--port", "8", "--path", "ui/"], FILE, ENV).unwrap();
+        assert_eq!(config.daemon.base_url(), "http://envhost:8/ui");
+        let config = resolve(&["--host", "clihost"], FILE, &[]).unwrap();
+        assert_eq!(config.daemon.base_url(), "http://clihost:1234/file");
+
+        assert_eq!(resolve(&["-v"], FILE, ENV).unwrap().log_level, "debug");
+        assert_eq!(resolve(&["-vv"], FILE, ENV).unwrap().log_level, "trace");
+    }
+
+    #[test]
+    fn url_conflicts_with_address_parts() {
+        for part in ["--host", "--port", "--path"] {
+            let args = ["pipeweaver-app", "--url", "http://clihost/", part, "1"];
+            assert!(Cli::try_parse_from(args).is_err());
+        }
+    }
+
+    #[test]
+    fn invalid_environment_is_an_error() {
+        assert!(resolve(&[], "", &[(GIVE_UP_ENV, "soon")]).is_err());
+        assert!(resolve(&[], "", &[(URL_ENV, "https://localhost/")]).is_err());
+        assert!(resolve(&["--url", "localhost"], "", &[]).is_err());
+    }
+
+    #[test]
+    fn empty_log_environment_is_ignored() {
+        let config = resolve(&[], FILE, &[(LOG_ENV, "")]).unwrap();
+        assert_eq!(config.log_level, "warn");
+    }
+
+    #[test]
+    fn missing_explicit_config_is_an_error() {
+        assert!(Config::load(&cli(&["--config", "/nonexistent/app.toml"])).is_err());
+    }
+
+    #[test]
+    fn from_url() {
+        let address = DaemonAddress::from_url("http://[::1]:8080/ui/").unwrap();
+        assert_eq!(address.host, "::1");
+        assert_eq!(address.port, 8080);
+        assert_eq!(address.authority(), "[::1]:8080");
+
+        let address = DaemonAddress::from_url("http://localhost").unwrap();
+        assert_eq!(address.port, 80);
+
+        assert!(DaemonAddress::from_url("https://localhost").is_err());
+        assert!(DaemonAddress::from_url("http:///path").is_err());
+    }
+
+    #[test]
+    fn normalise_path_keeps_one_leading_slash() {
+        assert_eq!(normalise_path(""), "");
+        assert_eq!(normalise_path("/"), "");
+        assert_eq!(normalise_path("ui"), "/ui");
+        assert_eq!(normalise_path("//ui/app//"), "/ui/app");
+    }
+}
@@ -0,0 +1,706 @@
+use crate::config::DaemonAddress;
+use json_patch::Patch;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex, PoisonError, mpsc};
+use std::time::{Duration, Instant};
+use std::{error, fmt};
+
+// How long callers wait for a response if they've no better idea
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The largest HTTP response body we'll accept, in bytes. The daemon's replies are far smaller,
+// anything bigger isn't something we want to hold in memory.
+const MAX_RESPONSE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Requests understood by the daemon
+#[derive(Serialize, Debug, Clone)]
+pub enum DaemonRequest {
+    Ping,
+    GetStatus,
+    Pipewire(PipewireCommand),
+
+    // Anything we don't have a type for (such as from the config), sent as-is
+    #[serde(untagged)]
+    Raw(Value),
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub enum PipewireCommand {
+    SetSourceVolume(String, Mix, u8),
+    SetTargetVolume(String, u8),
+    AddSourceMuteTarget(String, MuteTarget),
+    DelSourceMuteTarget(String, MuteTarget),
+    SetTargetMuteState(String, MuteState),
+}
+
+// The mixer only controls A, but this mirrors the daemon
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mix {
+    A,
+    B,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MuteTarget {
+    TargetA,
+    TargetB,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MuteState {
+    Muted,
+    Unmuted,
+}
+
+/// Responses (and pushed events) from the daemon
+#[derive(Deserialize, Debug, Clone)]
+pub enum DaemonResponse {
+    Ok,
+    Err(String),
+    Status(Value),
+    Patch(Patch),
+
+    // Anything we don't have a type for, so a new daemon doesn't break us
+    #[serde(untagged)]
+    Other(Value),
+}
+
+#[derive(Serialize, Debug)]
+struct WebsocketRequest<'a> {
+    id: u64,
+    data: &'a DaemonRequest,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WebsocketResponse {
+    pub id: u64,
+    pub data: DaemonResponse,
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    // We couldn't reach the daemon, or lost the connection before it responded
+    Disconnected,
+    Timeout,
+
+    // The daemon understood the request, but refused it
+    Daemon(String),
+
+    // The daemon's HTTP API returned an error status
+    Http(u16),
+    Protocol(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Disconnected => write!(f, "Not connected to Pipeweaver"),
+            ApiError::Timeout => write!(f, "Timed out waiting for Pipeweaver"),
+            ApiError::Daemon(e) => write!(f, "Pipeweaver returned an error: {e}"),
+            ApiError::Http(status) => write!(f, "Pipeweaver returned HTTP {status}"),
+            ApiError::Protocol(e) => write!(f, "Unexpected response from Pipeweaver: {e}"),
+            ApiError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl error::Error for ApiError {}
+
+impl From<io::Error> for ApiError {
+    fn from(e: io::Error) -> Self {
+        ApiError::Io(e)
+    }
+}
+
+pub type ApiResult = Result<DaemonResponse, ApiError>;
+
+/// A request waiting to be sent by the websocket thread
+pub struct ClientRequest {
+    pub request: DaemonRequest,
+    reply: Option<mpsc::Sender<ApiResult>>,
+    deadline: Instant,
+}
+
+impl ClientRequest {
+    /// A request that nobody is waiting on a response to
+    pub fn new(request: DaemonRequest) -> Self {
+        Self {
+            request,
+            reply: None,
+            deadline: Instant::now() + DEFAULT_TIMEOUT,
+        }
+    }
+
+    fn fail(self, error: ApiError) {
+        if let Some(reply) = self.reply {
+            let _ = reply.send(Err(error));
+        }
+    }
+}
+
+/// A handle for talking to the daemon over the websocket, which can be cloned and used from any
+/// thread. Requests made while the websocket isn't connected (including while we're waiting for
+/// the daemon to start) fail straight away with ApiError::Disconnected rather than being queued.
+#[derive(Clone)]
+pub struct DaemonClient {
+    tx: mpsc::Sender<ClientRequest>,
+
+    // Held while queueing, so a request can't slip in after the queue's been failed on disconnect
+    connected: Arc<Mutex<bool>>,
+
+    // Written to after each request, so the socket thread can sleep until there's work to do
+    wake: Arc<UnixStream>,
+}
+
+impl DaemonClient {
+    /// Returns the client, and the queue to hand to the websocket thread
+    pub fn new() -> io::Result<(Self, RequestQueue)> {
+        let (tx, rx) = mpsc::channel();
+        let (wake, waker) = UnixStream::pair()?;
+        wake.set_nonblocking(true)?;
+        waker.set_nonblocking(true)?;
+
+        let connected = Arc::new(Mutex::new(false));
+        let client = Self {
+            tx,
+            connected: connected.clone(),
+            wake: Arc::new(wake),
+        };
+        let queue = RequestQueue {
+            rx,
+            connected,
+            waker,
+        };
+        Ok((client, queue))
+    }
+
+    /// Sends a request without waiting for the daemon to respond
+    pub fn send(&self, request: DaemonRequest) -> Result<(), ApiError> {
+        self.queue(ClientRequest::new(request))
+    }
+
+    /// Sends a request, blocking for up to timeout for the daemon's response
+    pub fn request(&self, request: DaemonRequest, timeout: Duration) -> ApiResult {
+        let (reply_tx, reply_rx) = mpsc::channel();
+        self.queue(ClientRequest {
+            request,
+            reply: Some(reply_tx),
+            deadline: Instant::now() + timeout,
+        })?;
+
+        match reply_rx.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(ApiError::Timeout),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(ApiError::Disconnected),
+        }
+    }
+
+    fn queue(&self, request: ClientRequest) -> Result<(), ApiError> {
+        {
+            let connected = self
+                .connected
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if !*connected {
+                return Err(ApiError::Disconnected);
+            }
+            self.tx.send(request).map_err(|_| ApiError::Disconnected)?;
+        }
+
+        // If the buffer's full the socket thread already has a wakeup waiting
+        let _ = (&*self.wake).write(&[1]);
+        Ok(())
+    }
+}
+
+/// The websocket thread's end of a DaemonClient
+pub struct RequestQueue {
+    rx: mpsc::Receiver<ClientRequest>,
+    connected: Arc<Mutex<bool>>,
+    waker: UnixStream,
+}
+
+impl RequestQueue {
+    /// Becomes readable when there are requests to take
+    pub fn wake_fd(&self) -> RawFd {
+        self.waker.as_raw_fd()
+    }
+
+    /// Takes every request queued so far
+    pub fn drain(&self) -> mpsc::TryIter<'_, ClientRequest> {
+        // Clear the wakeups first, so a request queued while we're draining wakes us again
+        let mut buffer = [0; 64];
+        while matches!((&self.waker).read(&mut buffer), Ok(read) if read > 0) {}
+        self.rx.try_iter()
+    }
+
+    /// Called as the websocket connects and disconnects, so clients know whether to try
+    pub fn set_connected(&self, connected: bool) {
+        // Anything still queued was made before the connection dropped, so fail it rather than
+        // sending it to a daemon that may have restarted since. Clients can't queue anything
+        // while we hold the lock, so nothing is left behind once it's released.
+        let mut state = self
+            .connected
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *state = connected;
+        self.fail_queued();
+    }
+
+    fn fail_queued(&self) {
+        let mut failed = 0;
+        for request in self.drain() {
+            request.fail(ApiError::Disconnected);
+            failed += 1;
+        }
+        if failed > 0 {
+            warn!("Failed {failed} request(s) made while disconnected");
+        }
+    }
+}
+
+/// Tracks requests sent over a single websocket connection, matching responses to them by ID
+#[derive(Default)]
+pub struct PendingRequests {
+    next_id: u64,
+    pending: HashMap<u64, (mpsc::Sender<ApiResult>, Instant)>,
+}
+
+impl PendingRequests {
+    /// Allocates an ID for the request, and serialises it ready to send
+    pub fn prepare(&mut self, request: ClientRequest) -> Result<String, ApiError> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let message = serde_json::to_string(&WebsocketRequest {
+            id,
+            data: &request.request,
+        })
+        .map_err(|e| ApiError::Protocol(e.to_string()))?;
+
+        debug!("Sending request {id} to Pipeweaver");
+        if let Some(reply) = request.reply {
+            self.pending.insert(id, (reply, request.deadline));
+        }
+        Ok(message)
+    }
+
+    /// Passes the response on to whoever made the request, if anyone is waiting for it
+    pub fn resolve(&mut self, id: u64, response: DaemonResponse) {
+        let Some((reply, _)) = self.pending.remove(&id) else {
+            return;
+        };
+
+        let result = match response {
+            DaemonResponse::Err(e) => Err(ApiError::Daemon(e)),
+            response => Ok(response),
+        };
+        let _ = reply.send(result);
+    }
+
+    /// When the next request that someone is waiting on will expire
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().map(|(_, deadline)| *deadline).min()
+    }
+
+    /// Drops requests whose callers have stopped waiting
+    pub fn expire(&mut self) {
+        let now = Instant::now();
+        self.pending.retain(|id, (_, deadline)| {
+            if *deadline < now {
+                warn!("No response from Pipeweaver to request {id}");
+                return false;
+            }
+            true
+        });
+    }
+}
+
+/// Sends a single request to the daemon's HTTP API, for when there's no websocket to hand
+pub fn http_request(
+    address: &DaemonAddress,
+    request: &DaemonRequest,
+    timeout: Duration,
+) -> ApiResult {
+    let mut stream = (address.host.as_str(), address.port)
+        .to_socket_addrs()?
+        .find_map(|addr| TcpStream::connect_timeout(&addr, timeout).ok())
+        .ok_or(ApiError::Disconnected)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let body = serde_json::to_string(request).map_err(|e| ApiError::Protocol(e.to_string()))?;
+    let message = format!(
+        "POST {}/api/command HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        address.path,
+        address.authority(),
+        body.len()
+    );
+    stream.write_all(message.as_bytes())?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| ApiError::Protocol(format!("Bad status line: {}", status_line.trim())))?;
+
+    let mut chunked = false;
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+
+        let (name, value) = (name.trim(), value.trim());
+        if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        } else if name.eq_ignore_ascii_case("content-length") {
+            let value = value
+                .parse::<u64>()
+                .map_err(|_| ApiError::Protocol(format!("Bad content length: {value}")))?;
+            length = Some(value);
+        }
+    }
+
+    // Even though we ask the server to close the connection it might not, so only read to EOF if
+    // it hasn't told us how much to expect
+    let mut body = vec![];
+    if chunked {
+        read_chunked(&mut reader, &mut body)?;
+    } else if let Some(length) = length {
+        if length > MAX_RESPONSE_SIZE {
+            return Err(too_large());
+        }
+        reader.take(length).read_to_end(&mut body)?;
+        if (body.len() as u64) < length {
+            return Err(ApiError::Protocol("Response was cut short".into()));
+        }
+    } else {
+        reader.take(MAX_RESPONSE_SIZE + 1).read_to_end(&mut body)?;
+        if body.len() as u64 > MAX_RESPONSE_SIZE {
+            return Err(too_large());
+        }
+    }
+
+    if !(200..300).contains(&status) {
+        return Err(ApiError::Http(status));
+    }
+
+    match serde_json::from_slice(&body) {
+        Ok(DaemonResponse::Err(e)) => Err(ApiError::Daemon(e)),
+        Ok(response) => Ok(response),
+        Err(e) => Err(ApiError::Protocol(e.to_string())),
+    }
+}
+
+fn read_chunked(reader: &mut impl BufRead, body: &mut Vec<u8>) -> Result<(), ApiError> {
+    loop {
+        let mut size = String::new();
+        reader.read_line(&mut size)?;
+
+        // Chunk extensions (after a ';') aren't something we need
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = u64::from_str_radix(size, 16)
+            .map_err(|_| ApiError::Protocol(format!("Bad chunk size: {size}")))?;
+        if size == 0 {
+            return Ok(());
+        }
+
+        // The sizes come from the server, so don't trust them to add up to something sensible
+        let start = body.len();
+        let end = (start as u64)
+            .checked_add(size)
+            .filter(|&end| end <= MAX_RESPONSE_SIZE)
+            .ok_or_else(too_large)?;
+        body.resize(end as usize, 0);
+        reader.read_exact(&mut body[start..])?;
+
+        // Each chunk is followed by a CRLF
+        let mut crlf = [0; 2];
+        reader.read_exact(&mut crlf)?;
+    }
+}
+
+fn too_large() -> ApiError {
+    ApiError::Protocol(format!("Response is larger than {MAX_RESPONSE_SIZE} bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn waiting(deadline: Instant) -> (ClientRequest, mpsc::Receiver<ApiResult>) {
+        let (tx, rx) = mpsc::channel();
+        let request = ClientRequest {
+            request: DaemonRequest::Ping,
+            reply: Some(tx),
+            deadline,
+        };
+        (request, rx)
+    }
+
+    fn id_of(message: &str) -> u64 {
+        let message: Value = serde_json::from_str(message).unwrap();
+        message["id"].as_u64().unwrap()
+    }
+
+    #[test]
+    fn prepare_gives_each_request_its_own_id() {
+        let mut pending = PendingRequests::default();
+        let first = pending
+            .prepare(ClientRequest::new(DaemonRequest::Ping))
+            .unwrap();
+        let second = pending
+            .prepare(ClientRequest::new(DaemonRequest::GetStatus))
+            .unwrap();
+
+        assert_eq!(first, r#"{"id":0,"data":"Ping"}"#);
+        assert_eq!(second, r#"{"id":1,"data":"GetStatus"}"#);
+    }
+
+    #[test]
+    fn resolve_replies_to_the_matching_request() {
+        let mut pending = PendingRequests::default();
+        let (first, first_rx) = waiting(Instant::now() + TIMEOUT);
+        let (second, second_rx) = waiting(Instant::now() + TIMEOUT);
+        let first = id_of(&pending.prepare(first).unwrap());
+        let second = id_of(&pending.prepare(second).unwrap());
+
+        pending.resolve(second, DaemonResponse::Err("Unknown channel".into()));
+        pending.resolve(first, DaemonResponse::Ok);
+
+        assert!(matches!(first_rx.recv().unwrap(), Ok(DaemonResponse::Ok)));
+        assert!(matches!(
+            second_rx.recv().unwrap(),
+            Err(ApiError::Daemon(e)) if e == "Unknown channel"
+        ));
+        assert!(pending.next_deadline().is_none());
+    }
+
+    #[test]
+    fn resolve_ignores_requests_nobody_is_waiting_on() {
+        let mut pending = PendingRequests::default();
+        let id = id_of(
+            &pending
+                .prepare(ClientRequest::new(DaemonRequest::Ping))
+                .unwrap(),
+        );
+
+        assert!(pending.next_deadline().is_none());
+        pending.resolve(id, DaemonResponse::Ok);
+        pending.resolve(id + 1, DaemonResponse::Ok);
+    }
+
+    #[test]
+    fn expire_drops_only_overdue_requests() {
+        let mut pending = PendingRequests::default();
+        let later = Instant::now() + TIMEOUT;
+        let (overdue, overdue_rx) = waiting(Instant::now() - Duration::from_millis(1));
+        let (current, current_rx) = waiting(later);
+        pending.prepare(overdue).unwrap();
+        let current = id_of(&pending.prepare(current).unwrap());
+
+        pending.expire();
+        assert!(overdue_rx.recv().is_err());
+        assert_eq!(pending.next_deadline(), Some(later));
+
+        pending.resolve(current, DaemonResponse::Ok);
+        assert!(matches!(current_rx.recv().unwrap(), Ok(DaemonResponse::Ok)));
+    }
+
+    #[test]
+    fn client_fails_requests_while_disconnected() {
+        let (client, queue) = DaemonClient::new().unwrap();
+        assert!(matches!(
+            client.send(DaemonRequest::Ping),
+            Err(ApiError::Disconnected)
+        ));
+        assert!(matches!(
+            client.request(DaemonRequest::Ping, TIMEOUT),
+            Err(ApiError::Disconnected)
+        ));
+        assert_eq!(queue.drain().count(), 0);
+
+        queue.set_connected(true);
+        client.send(DaemonRequest::Ping).unwrap();
+        assert_eq!(queue.drain().count(), 1);
+
+        // Requests still queued when the connection drops are failed, not left waiting, however
+        // the request and the disconnect are ordered
+        let started = Instant::now();
+        for _ in 0..100 {
+            queue.set_connected(true);
+            let client = client.clone();
+            let waiter = thread::spawn(move || client.request(DaemonRequest::Ping, TIMEOUT));
+            queue.set_connected(false);
+            assert!(matches!(
+                waiter.join().unwrap(),
+                Err(ApiError::Disconnected)
+            ));
+        }
+        assert!(started.elapsed() < TIMEOUT);
+    }
+
+    #[test]
+    fn read_chunked_joins_chunks() {
+        let mut body = vec![];
+        let mut input = "3;name=value\r\nabc\r\n2\r\nde\r\n0\r\n\r\n".as_bytes();
+        read_chunked(&mut input, &mut body).unwrap();
+        assert_eq!(body, b"abcde");
+    }
+
+    #[test]
+    fn read_chunked_rejects_bad_sizes() {
+        let mut body = vec![];
+        let mut input = "zz\r\nabc\r\n".as_bytes();
+        assert!(matches!(
+            read_chunked(&mut input, &mut body),
+            Err(ApiError::Protocol(_))
+        ));
+    }
+
+    #[test]
+    fn read_chunked_limits_the_size() {
+        let mut body = vec![];
+        let mut input = "ffffffffffffffff\r\nabc\r\n".as_bytes();
+        assert!(matches!(
+            read_chunked(&mut input, &mut body),
+            Err(ApiError::Protocol(_))
+        ));
+        assert!(body.is_empty());
+
+        // Each chunk is small, but together they're too much
+        let mut body = vec![0; MAX_RESPONSE_SIZE as usize - 2];
+        let mut input = "2\r\nab\r\n1\r\nc\r\n0\r\n\r\n".as_bytes();
+        assert!(matches!(
+            read_chunked(&mut input, &mut body),
+            Err(ApiError::Protocol(_))
+        ));
+        assert_eq!(body.len(), MAX_RESPONSE_SIZE as usize);
+    }
+
+    // Answers a single request with the response, then holds the connection open like a server
+    // ignoring 'Connection: close' would. Returns the address, and the request as it was received.
+    fn serve(response: &'static str) -> (DaemonAddress, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = DaemonAddress {
+            host: "127.0.0.1".into(),
+            port: listener.local_addr().unwrap().port(),
+            path: String::new(),
+        };
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut request = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.strip_prefix("Content-Length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+            let _ = tx.send(request);
+
+            stream.write_all(response.as_bytes()).unwrap();
+            thread::sleep(TIMEOUT * 2);
+        });
+        (address, rx)
+    }
+
+    #[test]
+    fn http_request_reads_content_length_without_waiting_for_close() {
+        let (mut address, request) = serve("HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\n\"Ok\"");
+        address.path = "/pipeweaver".into();
+
+        let started = Instant::now();
+        let response = http_request(&address, &DaemonRequest::Ping, TIMEOUT);
+        assert!(matches!(response, Ok(DaemonResponse::Ok)));
+        assert!(started.elapsed() < TIMEOUT);
+
+        let request = request.recv().unwrap();
+        assert!(request.starts_with("POST /pipeweaver/api/command HTTP/1.1\r\n"));
+        assert!(request.ends_with("\r\n\r\n\"Ping\""));
+    }
+
+    #[test]
+    fn http_request_reads_chunked_responses() {
+        let (address, _) = serve(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n\"O\r\n2\r\nk\"\r\n0\r\n\r\n",
+        );
+        let response = http_request(&address, &DaemonRequest::Ping, TIMEOUT);
+        assert!(matches!(response, Ok(DaemonResponse::Ok)));
+    }
+
+    #[test]
+    fn http_request_reports_errors() {
+        let (address, _) = serve("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        let response = http_request(&address, &DaemonRequest::Ping, TIMEOUT);
+        assert!(matches!(response, Err(ApiError::Http(404))));
+
+        let (address, _) = serve("HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\n{\"Err\":\"no\"}");
+        let response = http_request(&address, &DaemonRequest::Ping, TIMEOUT);
+        assert!(matches!(response, Err(ApiError::Daemon(e)) if e == "no"));
+
+        let (address, _) = serve("HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\n{{{");
+        let response = http_request(&address, &DaemonRequest::Ping, TIMEOUT);
+        assert!(matches!(response, Err(ApiError::Protocol(_))));
+    }
+
+    #[test]
+    fn http_request_rejects_huge_responses() {
+        let (address, _) = serve("HTTP/1.1 200 OK\r\nContent-Length: 99999999999\r\n\r\n");
+        let started = Instant::now();
+        let response = http_request(&address, &DaemonRequest::Ping, TIMEOUT);
+        assert!(matches!(response, Err(ApiError::Protocol(_))));
+        assert!(started.elapsed() < TIMEOUT);
+    }
+
+    #[test]
+    fn http_request_without_a_server_is_disconnected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = DaemonAddress {
+            host: "127.0.0.1".into(),
+            port: listener.local_addr().unwrap().port(),
+            path: String::new(),
+        };
+        drop(listener);
+
+        let response = http_request(&address, &DaemonRequest::Ping, TIMEOUT);
+        assert!(matches!(response, Err(ApiError::Disconnected)));
+    }
+}
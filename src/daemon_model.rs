@@ -0,0 +1,73 @@
+use crate::daemon_state::{Channel, DaemonState};
+use qmetaobject::prelude::*;
+use qttypes::{QVariantList, QVariantMap};
+
+/// The state mirrored from the daemon, for use by QML
+#[derive(Default, QObject)]
+pub struct DaemonModel {
+    base: qt_base_class!(trait QObject),
+
+    // The active profile, empty if the daemon doesn't report one
+    profile: qt_property!(QString; NOTIFY state_changed),
+
+    // One map per channel, with the keys id, name, kind, physical, volume and muted
+    channels: qt_property!(QVariantList; NOTIFY state_changed),
+
+    state_changed: qt_signal!(),
+
+    // Emitted when an existing channel's volume or mute state changes
+    channel_changed: qt_signal!(id: QString, name: QString, volume: i32, muted: bool),
+
+    // The last state we were given, used to work out what's changed
+    state: DaemonState,
+}
+
+impl DaemonModel {
+    /// Updates the properties, returning the channels whose volume or mute state changed. The
+    /// signals need emitting afterwards, once the model is no longer mutably borrowed.
+    pub fn set_state(&mut self, state: &DaemonState) -> Vec<Channel> {
+        let changed = state
+            .channels
+            .iter()
+            .filter(|channel| {
+                self.state.channels.iter().any(|previous| {
+                    previous.id == channel.id
+                        && (previous.volume != channel.volume || previous.muted != channel.muted)
+                })
+            })
+            .cloned()
+            .collect();
+
+        self.profile = state.profile.clone().unwrap_or_default().into();
+        self.channels = state
+            .channels
+            .iter()
+            .map(|channel| {
+                QVariantMap::from([
+                    ("id", QVariant::from(QString::from(channel.id.as_str()))),
+                    ("name", QString::from(channel.name.as_str()).into()),
+                    ("kind", QString::from(channel.kind.as_str()).into()),
+                    ("physical", channel.physical.into()),
+                    ("volume", i32::from(channel.volume).into()),
+                    ("muted", channel.muted.into()),
+                ])
+            })
+            .collect();
+
+        self.state = state.clone();
+        changed
+    }
+
+    pub fn emit_state_changed(&self) {
+        self.state_changed();
+    }
+
+    pub fn emit_channel_changed(&self, channel: &Channel) {
+        self.channel_changed(
+            channel.id.as_str().into(),
+            channel.name.as_str().into(),
+            channel.volume.into(),
+            channel.muted,
+        );
+    }
+}
@@ -0,0 +1,406 @@
+use crate::daemon_api::{Mix, MuteState, MuteTarget};
+use json_patch::Patch;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A typed view of the parts of the daemon's status the app cares about
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DaemonState {
+    pub profile: Option<String>,
+    pub channels: Vec<Channel>,
+
+    // The audio hardware Pipewire currently has, whether or not the profile uses it
+    pub devices: Vec<Device>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Channel {
+    pub id: String,
+    pub name: String,
+    pub kind: ChannelKind,
+
+    // Physical channels are backed by hardware, virtual ones are created by the user
+    pub physical: bool,
+    pub volume: u8,
+    pub muted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Device {
+    // Pipewire's node name, which stays the same while the device is plugged in
+    pub id: String,
+    pub name: String,
+    pub kind: ChannelKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelKind {
+    Source,
+    Target,
+}
+
+impl ChannelKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChannelKind::Source => "source",
+            ChannelKind::Target => "target",
+        }
+    }
+}
+
+/// The result of handling a message from the daemon
+#[derive(Debug, PartialEq, Eq)]
+pub enum Update {
+    Unchanged,
+    Changed,
+
+    // A patch couldn't be applied, so the full status needs fetching again
+    OutOfSync,
+}
+
+/// Keeps a copy of the daemon's full status, updated from the Status and Patch messages it sends
+/// over the websocket.
+#[derive(Default)]
+pub struct DaemonMirror {
+    status: Option<Value>,
+    state: DaemonState,
+
+    // So a status we can't read is only reported once, rather than on every patch
+    unreadable: bool,
+}
+
+impl DaemonMirror {
+    pub fn state(&self) -> &DaemonState {
+        &self.state
+    }
+
+    /// Replaces our copy with the full status from the daemon
+    pub fn set_status(&mut self, status: Value) -> Update {
+        debug!("Received full status from Pipeweaver");
+        self.status = Some(status);
+        self.unreadable = false;
+        self.refresh()
+    }
+
+    pub fn apply_patch(&mut self, patch: &Patch) -> Update {
+        // Patches are relative to a status we've not seen yet, ignore them until we have
+        let Some(status) = &mut self.status else {
+            return Update::Unchanged;
+        };
+
+        if let Err(e) = json_patch::patch(status, patch) {
+            warn!("Unable to apply patch from Pipeweaver: {e}");
+            self.status = None;
+            return Update::OutOfSync;
+        }
+        self.refresh()
+    }
+
+    fn refresh(&mut self) -> Update {
+        let state = match self.status.as_ref().map(Status::deserialize) {
+            Some(Ok(status)) => DaemonState::from(status),
+            Some(Err(e)) => {
+                if !self.unreadable {
+                    warn!("Unable to read the status from Pipeweaver, is it a newer version? {e}");
+                    self.unreadable = true;
+                }
+                DaemonState::default()
+            }
+            None => DaemonState::default(),
+        };
+        if state == self.state {
+            return Update::Unchanged;
+        }
+
+        self.state = state;
+        Update::Changed
+    }
+}
+
+// The parts of the daemon's status we use, matching its DaemonStatus type. Anything else in it is
+// ignored, but if one of these fields moves or changes type the whole status fails to parse.
+#[derive(Deserialize)]
+struct Status {
+    audio: AudioConfiguration,
+}
+
+#[derive(Deserialize)]
+struct AudioConfiguration {
+    profile: Profile,
+
+    // Older daemons don't report it
+    #[serde(default)]
+    devices: HashMap<DeviceType, Vec<PipewireNode>>,
+}
+
+#[derive(Deserialize, PartialEq, Eq, Hash)]
+enum DeviceType {
+    Source,
+    Target,
+}
+
+#[derive(Deserialize)]
+struct PipewireNode {
+    name: Option<String>,
+    nickname: Option<String>,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Profile {
+    // Not every profile has a name
+    #[serde(default)]
+    name: Option<String>,
+    devices: Devices,
+}
+
+#[derive(Deserialize)]
+struct Devices {
+    sources: DeviceList<SourceDevice>,
+    targets: DeviceList<TargetDevice>,
+}
+
+#[derive(Deserialize)]
+struct DeviceList<T> {
+    physical_devices: Vec<T>,
+    virtual_devices: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct DeviceDescription {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct SourceDevice {
+    description: DeviceDescription,
+    mute_states: SourceMuteStates,
+    volumes: SourceVolumes,
+}
+
+// Sources can be muted to each target separately
+#[derive(Deserialize)]
+struct SourceMuteStates {
+    mute_state: Vec<MuteTarget>,
+}
+
+// Sources have a volume for each mix
+#[derive(Deserialize)]
+struct SourceVolumes {
+    volume: HashMap<Mix, u8>,
+}
+
+#[derive(Deserialize)]
+struct TargetDevice {
+    description: DeviceDescription,
+    mute_state: MuteState,
+    volume: u8,
+}
+
+impl From<Status> for DaemonState {
+    fn from(status: Status) -> Self {
+        let mut hardware = vec![];
+        for (kind, nodes) in status.audio.devices {
+            let kind = match kind {
+                DeviceType::Source => ChannelKind::Source,
+                DeviceType::Target => ChannelKind::Target,
+            };
+            for node in nodes {
+                // Without a node name there's nothing to recognise it by next time
+                let Some(id) = node.name else {
+                    continue;
+                };
+                let name = node
+                    .description
+                    .or(node.nickname)
+                    .unwrap_or_else(|| id.clone());
+                hardware.push(Device { id, name, kind });
+            }
+        }
+        // The map has no order, keep the state stable so it only changes when the devices do
+        hardware.sort_by(|a, b| (a.kind.as_str(), &a.id).cmp(&(b.kind.as_str(), &b.id)));
+
+        let Profile { name, devices } = status.audio.profile;
+        let mut channels = vec![];
+
+        let sources = devices
+            .sources
+            .physical_devices
+            .into_iter()
+            .map(|d| (d, true));
+        let virtual_sources = devices.sources.virtual_devices.into_iter();
+        for (device, physical) in sources.chain(virtual_sources.map(|d| (d, false))) {
+            channels.push(Channel {
+                id: device.description.id,
+                name: device.description.name,
+                kind: ChannelKind::Source,
+                physical,
+
+                // The mixer controls mix A, so that's the one we report
+                volume: device
+                    .volumes
+                    .volume
+                    .get(&Mix::A)
+                    .copied()
+                    .unwrap_or_default(),
+                muted: !device.mute_states.mute_state.is_empty(),
+            });
+        }
+
+        let targets = devices
+            .targets
+            .physical_devices
+            .into_iter()
+            .map(|d| (d, true));
+        let virtual_targets = devices.targets.virtual_devices.into_iter();
+        for (device, physical) in targets.chain(virtual_targets.map(|d| (d, false))) {
+            channels.push(Channel {
+                id: device.description.id,
+                name: device.description.name,
+                kind: ChannelKind::Target,
+                physical,
+                volume: device.volume,
+                muted: device.mute_state == MuteState::Muted,
+            });
+        }
+
+        DaemonState {
+            profile: name,
+            channels,
+            devices: hardware,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status() -> Value {
+        serde_json::from_str(include_str!("../testdata/status.json")).unwrap()
+    }
+
+    fn patch(patch: Value) -> Patch {
+        serde_json::from_value(patch).unwrap()
+    }
+
+    fn channel(name: &str, kind: ChannelKind, physical: bool, volume: u8, muted: bool) -> Channel {
+        Channel {
+            id: String::new(),
+            name: name.into(),
+            kind,
+            physical,
+            volume,
+            muted,
+        }
+    }
+
+    #[test]
+    fn reads_the_daemon_status() {
+        let mut mirror = DaemonMirror::default();
+        assert_eq!(mirror.set_status(status()), Update::Changed);
+
+        let state = mirror.state();
+        assert_eq!(state.profile.as_deref(), Some("Streaming"));
+        assert_eq!(state.channels[0].id, "01JF2Y8Q4ZB6V7XK1D3M9N0PQR");
+
+        let channels: Vec<_> = state
+            .channels
+            .iter()
+            .map(|channel| Channel {
+                id: String::new(),
+                ..channel.clone()
+            })
+            .collect();
+        assert_eq!(
+            channels,
+            vec![
+                channel("Microphone", ChannelKind::Source, true, 80, true),
+                channel("System", ChannelKind::Source, false, 100, false),
+                channel("Headphones", ChannelKind::Target, true, 70, false),
+                channel("Stream Mix", ChannelKind::Target, false, 90, true),
+            ]
+        );
+
+        let devices: Vec<_> = state
+            .devices
+            .iter()
+            .map(|device| (device.kind, device.name.as_str()))
+            .collect();
+        assert_eq!(
+            devices,
+            vec![
+                (ChannelKind::Source, "Scarlett Solo Analog Stereo"),
+                (ChannelKind::Target, "Built-in Audio Analog Stereo"),
+            ]
+        );
+    }
+
+    #[test]
+    fn devices_are_optional() {
+        let mut status = status();
+        status["audio"].as_object_mut().unwrap().remove("devices");
+
+        let mut mirror = DaemonMirror::default();
+        mirror.set_status(status);
+        assert!(mirror.state().devices.is_empty());
+        assert_eq!(mirror.state().channels.len(), 4);
+    }
+
+    #[test]
+    fn applies_patches() {
+        let mut mirror = DaemonMirror::default();
+        mirror.set_status(status());
+
+        let volume = patch(serde_json::json!([{
+            "op": "replace",
+            "path": "/audio/profile/devices/targets/physical_devices/0/volume",
+            "value": 20
+        }]));
+        assert_eq!(mirror.apply_patch(&volume), Update::Changed);
+        assert_eq!(mirror.state().channels[2].volume, 20);
+
+        // Parts of the status we don't use don't count as a change
+        let colour = patch(serde_json::json!([{
+            "op": "replace",
+            "path": "/audio/profile/devices/targets/physical_devices/0/description/colour/red",
+            "value": 1
+        }]));
+        assert_eq!(mirror.apply_patch(&colour), Update::Unchanged);
+    }
+
+    #[test]
+    fn ignores_patches_before_the_status() {
+        let mut mirror = DaemonMirror::default();
+        let update = patch(serde_json::json!([{ "op": "add", "path": "/audio", "value": 1 }]));
+        assert_eq!(mirror.apply_patch(&update), Update::Unchanged);
+    }
+
+    #[test]
+    fn refetches_when_a_patch_does_not_apply() {
+        let mut mirror = DaemonMirror::default();
+        mirror.set_status(status());
+
+        let missing = patch(serde_json::json!([{
+            "op": "replace",
+            "path": "/audio/profile/devices/targets/physical_devices/5/volume",
+            "value": 20
+        }]));
+        assert_eq!(mirror.apply_patch(&missing), Update::OutOfSync);
+    }
+
+    #[test]
+    fn an_unreadable_status_is_empty() {
+        let mut status = status();
+        status["audio"]["profile"]["devices"]["targets"]["physical_devices"][0]["volume"] =
+            "loud".into();
+
+        let mut mirror = DaemonMirror::default();
+        mirror.set_status(status);
+        assert_eq!(mirror.state(), &DaemonState::default());
+    }
+}
@@ -0,0 +1,181 @@
+use crate::ipc;
+use crate::window_handler::{AppStatus, WindowMessage};
+use anyhow::{Result, bail};
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, mpsc};
+use zbus::blocking::Connection;
+use zbus::blocking::connection::Builder;
+use zbus::fdo::{self, RequestNameFlags, RequestNameReply};
+use zbus::interface;
+use zbus::zvariant::{OwnedValue, Value};
+
+// The name is D-Bus activatable through resources/io.github.pipeweaver.App.service, so
+// org.freedesktop.Application callers can start the app as well as raise it
+const BUS_NAME: &str = "io.github.pipeweaver.App";
+
+// org.freedesktop.Application requires the object path to match the bus name
+const OBJECT_PATH: &str = "/io/github/pipeweaver/App";
+
+/// Our own interface, for controlling the window from scripts and shortcuts
+struct AppInterface {
+    tx: mpsc::Sender<WindowMessage>,
+    status: Arc<AppStatus>,
+}
+
+impl AppInterface {
+    fn send(&self, message: WindowMessage) -> fdo::Result<()> {
+        self.tx
+            .send(message)
+            .map_err(|_| fdo::Error::Failed("Window is no longer running".into()))
+    }
+}
+
+#[interface(name = "io.github.pipeweaver.App")]
+impl AppInterface {
+    fn show(&self) -> fdo::Result<()> {
+        self.send(WindowMessage::Trigger)
+    }
+
+    fn hide(&self) -> fdo::Result<()> {
+        self.send(WindowMessage::Hide)
+    }
+
+    fn toggle(&self) -> fdo::Result<()> {
+        self.send(WindowMessage::Toggle)
+    }
+
+    fn quit(&self) -> fdo::Result<()> {
+        self.send(WindowMessage::Quit)
+    }
+
+    fn reload(&self) -> fdo::Result<()> {
+        self.send(WindowMessage::Reload)
+    }
+
+    fn navigate(&self, path: String) -> fdo::Result<()> {
+        let message = ipc::navigate(path).map_err(|e| fdo::Error::InvalidArgs(e.into()))?;
+        self.send(message)
+    }
+
+    #[zbus(property)]
+    fn connected(&self) -> bool {
+        self.status.connected.load(Ordering::Relaxed)
+    }
+
+    #[zbus(property)]
+    fn visible(&self) -> bool {
+        self.status.visible.load(Ordering::Relaxed)
+    }
+}
+
+/// The standard activation interface, used by desktops which launch apps over D-Bus
+struct ApplicationInterface {
+    tx: mpsc::Sender<WindowMessage>,
+}
+
+impl ApplicationInterface {
+    fn send(&self, platform_data: &HashMap<String, OwnedValue>, message: WindowMessage) {
+        // Either of these lets us take focus on Wayland, depending on the desktop
+        let token = ["activation-token", "desktop-startup-id"]
+            .iter()
+            .find_map(|key| match platform_data.get(*key).map(|v| &**v) {
+                Some(Value::Str(token)) => Some(token.to_string()),
+                _ => None,
+            });
+
+        if let Some(token) = token {
+            let _ = self.tx.send(WindowMessage::ActivationToken(token));
+        }
+        let _ = self.tx.send(message);
+    }
+}
+
+#[interface(name = "org.freedesktop.Application")]
+impl ApplicationInterface {
+    fn activate(&self, platform_data: HashMap<String, OwnedValue>) {
+        debug!("Activated over D-Bus");
+        self.send(&platform_data, WindowMessage::Trigger);
+    }
+
+    // We don't handle any files, so treat this the same as being activated
+    fn open(&self, _uris: Vec<String>, platform_data: HashMap<String, OwnedValue>) {
+        self.send(&platform_data, WindowMessage::Trigger);
+    }
+
+    fn activate_action(
+        &self,
+        action_name: &str,
+        _parameter: Vec<OwnedValue>,
+        platform_data: HashMap<String, OwnedValue>,
+    ) -> fdo::Result<()> {
+        let message = match action_name {
+            "show" => WindowMessage::Trigger,
+            "hide" => WindowMessage::Hide,
+            "toggle" => WindowMessage::Toggle,
+            "reload" => WindowMessage::Reload,
+            "quit" => WindowMessage::Quit,
+            _ => {
+                return Err(fdo::Error::InvalidArgs(format!(
+                    "Unknown action {action_name}"
+                )));
+            }
+        };
+        self.send(&platform_data, message);
+        Ok(())
+    }
+}
+
+/// Owns our name on the session bus, and is used by the Qt side to push property changes
+pub struct DbusService {
+    connection: Connection,
+}
+
+impl DbusService {
+    pub fn spawn(tx: mpsc::Sender<WindowMessage>, status: Arc<AppStatus>) -> Result<Self> {
+        let app = AppInterface {
+            tx: tx.clone(),
+            status,
+        };
+        let application = ApplicationInterface { tx };
+
+        let connection = Builder::session()?
+            .serve_at(OBJECT_PATH, app)?
+            .serve_at(OBJECT_PATH, application)?
+            .build()?;
+
+        // Don't allow anything else to take the name from us, or queue behind someone else
+        let reply =
+            connection.request_name_with_flags(BUS_NAME, RequestNameFlags::DoNotQueue.into())?;
+        if reply != RequestNameReply::PrimaryOwner {
+            bail!("{BUS_NAME} is already owned by another process");
+        }
+
+        debug!("Registered {BUS_NAME} on the session bus");
+        Ok(Self { connection })
+    }
+
+    pub fn connected_changed(&self) {
+        self.notify(|iface, emitter| zbus::block_on(iface.connected_changed(emitter)));
+    }
+
+    pub fn visible_changed(&self) {
+        self.notify(|iface, emitter| zbus::block_on(iface.visible_changed(emitter)));
+    }
+
+    fn notify<F>(&self, emit: F)
+    where
+        F: FnOnce(&AppInterface, &zbus::object_server::SignalEmitter<'_>) -> zbus::Result<()>,
+    {
+        let result = self
+            .connection
+            .object_server()
+            .interface::<_, AppInterface>(OBJECT_PATH)
+            .and_then(|iface| emit(&iface.get(), iface.signal_emitter()));
+
+        if let Err(e) = result {
+            warn!("Unable to emit D-Bus property change: {e}");
+        }
+    }
+}
@@ -0,0 +1,818 @@
+use crate::APP_NAME;
+use crate::config::Cli;
+use crate::daemon_state::DaemonState;
+use crate::window_handler::{AppStatus, WindowMessage};
+use anyhow::{Result, anyhow, bail};
+use clap::Parser;
+use dirs::runtime_dir;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{DirBuilder, File, OpenOptions, Permissions, TryLockError};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::net::Shutdown;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use std::{env, fs, process, thread};
+
+/// The current version of the IPC protocol, bumped on breaking changes
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// How long a second instance waits for the lock holder to start accepting connections
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How long we'll wait on a client to send (or receive) data before dropping it
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The longest we'll keep any one connection open, however busy the client keeps it
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+// The largest single message we'll accept from a client, in bytes
+const MAX_MESSAGE_SIZE: u64 = 64 * 1024;
+
+/// A single request, sent as one line of JSON, for example:
+/// {"version": 1, "command": "navigate", "path": "/mixer"}
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IpcRequest {
+    pub version: u32,
+
+    #[serde(flatten)]
+    pub command: IpcCommand,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum IpcCommand {
+    // The activation token (if provided) allows the window to take focus on Wayland
+    Show {
+        #[serde(default)]
+        activation_token: Option<String>,
+    },
+    Hide,
+    Toggle {
+        #[serde(default)]
+        activation_token: Option<String>,
+    },
+    Quit,
+    Reload,
+    Navigate {
+        path: String,
+    },
+    Status,
+
+    // The command line of another launch of the app, to be acted on by this instance
+    Args {
+        argv: Vec<String>,
+        cwd: PathBuf,
+
+        #[serde(default)]
+        activation_token: Option<String>,
+    },
+}
+
+/// The outcome of handing our command line to an already running instance
+pub enum ActiveInstance {
+    Accepted,
+    Rejected(String),
+}
+
+/// An exclusive lock on the lock file, held for the life of the process. Only the holder of this
+/// lock may create (or remove) the IPC socket.
+pub struct InstanceLock {
+    file: File,
+}
+
+impl InstanceLock {
+    /// Attempts to take the lock, returning None if another instance already holds it
+    pub fn acquire() -> Result<Option<Self>> {
+        let path = get_lock_file_path()?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .open(&path)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let pid = read_pid(&mut file);
+                debug!("Lock {path:?} is held by another instance (pid {pid:?})");
+                return Ok(None);
+            }
+            Err(TryLockError::Error(e)) => bail!("Unable to lock {path:?}: {e}"),
+        }
+
+        // The kernel releases the lock when a process dies, so if there's a PID left behind in
+        // the file, the previous instance didn't shut down cleanly.
+        if let Some(pid) = read_pid(&mut file) {
+            warn!("Found stale lock from pid {pid}, previous instance did not exit cleanly");
+        }
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write!(file, "{}", process::id())?;
+        file.flush()?;
+
+        debug!("Acquired instance lock at {path:?}");
+        Ok(Some(Self { file }))
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // Clear our PID so the next instance knows we exited cleanly, the lock file itself is
+        // left in place, removing it would allow two instances to lock different files.
+        let _ = self.file.set_len(0);
+    }
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut content = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut content).ok()?;
+    content.trim().parse().ok()
+}
+
+/// Sent back as a single line of JSON for each request
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct IpcResponse {
+    pub version: u32,
+    pub ok: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<IpcStatus>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IpcStatus {
+    pub connected: bool,
+    pub visible: bool,
+    pub app_version: String,
+
+    // The last state mirrored from the daemon, older clients won't send this
+    #[serde(default)]
+    pub daemon: DaemonState,
+}
+
+impl IpcResponse {
+    fn ok() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            ok: true,
+            ..Default::default()
+        }
+    }
+
+    fn error(message: impl Into<String>) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            ok: false,
+            error: Some(message.into()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Binds the IPC socket, this should only be called while holding the InstanceLock
+pub fn bind_socket() -> Result<UnixListener> {
+    let socket_path = get_socket_file_path()?;
+
+    // As we hold the lock, anything already here was left behind by a previous instance
+    if socket_path.exists() {
+        debug!("Removing Stale Socket File");
+        let _ = fs::remove_file(&socket_path);
+    }
+
+    let listener = match UnixListener::bind(&socket_path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!("Failed to bind to socket: {e}");
+            bail!("Failed to bind to socket: {e}");
+        }
+    };
+
+    // The directory is already private, but make sure the socket is too
+    fs::set_permissions(&socket_path, Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+pub fn ipc_thread_main(
+    listener: UnixListener,
+    tx: mpsc::Sender<WindowMessage>,
+    status: Arc<AppStatus>,
+) -> Result<()> {
+    debug!("Spawning IPC Socket Handler");
+    let socket_path = get_socket_file_path()?;
+
+    debug!("IPC listener started at {socket_path:?}");
+    loop {
+        // This blocks until a client connects, each is then handled on its own thread so a slow
+        // client can't hold up anyone else
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let tx = tx.clone();
+                let status = status.clone();
+                thread::spawn(move || {
+                    if let Err(e) = handle_client(stream, &tx, &status) {
+                        warn!("Failed to handle IPC client: {e}");
+                    }
+                });
+            }
+            Err(e) => {
+                warn!("Unexpected socket error: {e}");
+                break;
+            }
+        }
+    }
+    let _ = fs::remove_file(&socket_path);
+    debug!("IPC Socket closed (thread)");
+    Ok(())
+}
+
+fn handle_client(
+    stream: UnixStream,
+    tx: &mpsc::Sender<WindowMessage>,
+    status: &AppStatus,
+) -> Result<()> {
+    // Only accept commands from processes running as our user
+    let uid = peer_uid(&stream)?;
+    if uid != current_uid() {
+        bail!("Rejected connection from uid {uid}");
+    }
+
+    // Don't let a client that stops listening keep the connection open
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(DeadlineReader {
+        stream,
+        deadline: Instant::now() + CONNECTION_TIMEOUT,
+    });
+    loop {
+        let mut line = String::new();
+        let read = reader
+            .by_ref()
+            .take(MAX_MESSAGE_SIZE + 1)
+            .read_line(&mut line)?;
+
+        if read == 0 {
+            break;
+        }
+
+        if read as u64 > MAX_MESSAGE_SIZE {
+            let response = IpcResponse::error("Message too large");
+            let _ = writeln!(writer, "{}", serde_json::to_string(&response)?);
+            bail!("Client sent a message larger than {MAX_MESSAGE_SIZE} bytes");
+        }
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<IpcRequest>(line) {
+            Ok(request) if request.version > PROTOCOL_VERSION => {
+                IpcResponse::error(format!("Unsupported protocol version {}", request.version))
+            }
+            Ok(request) => handle_command(request.command, tx, status),
+            Err(e) => IpcResponse::error(format!("Invalid request: {e}")),
+        };
+
+        let mut json = serde_json::to_string(&response)?;
+        json.push('\n');
+        writer.write_all(json.as_bytes())?;
+    }
+    Ok(())
+}
+
+// Reads from a client until the connection's deadline, however slowly it sends, as well as
+// dropping it if it goes quiet for CLIENT_TIMEOUT
+struct DeadlineReader {
+    stream: UnixStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(
+                ErrorKind::TimedOut,
+                "Client kept the connection open too long",
+            ));
+        }
+
+        self.stream
+            .set_read_timeout(Some(remaining.min(CLIENT_TIMEOUT)))?;
+        self.stream.read(buf)
+    }
+}
+
+fn handle_command(
+    command: IpcCommand,
+    tx: &mpsc::Sender<WindowMessage>,
+    status: &AppStatus,
+) -> IpcResponse {
+    debug!("Received IPC Command: {command:?}");
+    let messages = match command {
+        IpcCommand::Show { activation_token } => {
+            with_activation(activation_token, WindowMessage::Trigger)
+        }
+        IpcCommand::Hide => vec![WindowMessage::Hide],
+        IpcCommand::Toggle { activation_token } => {
+            with_activation(activation_token, WindowMessage::Toggle)
+        }
+        IpcCommand::Quit => vec![WindowMessage::Quit],
+        IpcCommand::Reload => vec![WindowMessage::Reload],
+        IpcCommand::Navigate { path } => match navigate(path) {
+            Ok(message) => vec![message],
+            Err(e) => return IpcResponse::error(e),
+        },
+        IpcCommand::Status => {
+            return IpcResponse {
+                status: Some(IpcStatus {
+                    connected: status.connected.load(Ordering::Relaxed),
+                    visible: status.visible.load(Ordering::Relaxed),
+                    app_version: env!("CARGO_PKG_VERSION").to_string(),
+                    daemon: status
+                        .daemon
+                        .lock()
+                        .map(|daemon| daemon.clone())
+                        .unwrap_or_default(),
+                }),
+                ..IpcResponse::ok()
+            };
+        }
+        IpcCommand::Args {
+            argv,
+            cwd,
+            activation_token,
+        } => match args_to_messages(&argv, activation_token) {
+            Ok(messages) => {
+                debug!("Handling arguments from launch in {cwd:?}");
+                messages
+            }
+            Err(e) => return IpcResponse::error(e.to_string()),
+        },
+    };
+
+    send_messages(tx, messages)
+}
+
+/// Builds the message to navigate to a path, which must be within the Pipeweaver UI
+pub fn navigate(path: String) -> Result<WindowMessage, &'static str> {
+    if !path.starts_with('/') {
+        return Err("Path must start with '/'");
+    }
+    Ok(WindowMessage::Navigate(path))
+}
+
+/// Prefixes a message with the activation token, so it's in place before the window activates
+fn with_activation(token: Option<String>, message: WindowMessage) -> Vec<WindowMessage> {
+    let mut messages = vec![];
+    if let Some(token) = token {
+        messages.push(WindowMessage::ActivationToken(token));
+    }
+    messages.push(message);
+    messages
+}
+
+/// Works out what the window should do in response to another launch of the app. Options that
+/// only affect startup (such as the daemon address) are ignored, as we're already running.
+fn args_to_messages(argv: &[String], token: Option<String>) -> Result<Vec<WindowMessage>> {
+    let cli = Cli::try_parse_from(argv)?;
+
+    let mut messages = if cli.mixer {
+        with_activation(token, WindowMessage::ShowMixer)
+    } else if cli.toggle {
+        with_activation(token, WindowMessage::Toggle)
+    } else if cli.hidden {
+        // Such as the autostart entry, when we're already running there's nothing to do
+        vec![]
+    } else {
+        with_activation(token, WindowMessage::Trigger)
+    };
+
+    if let Some(path) = cli.page_path() {
+        messages.push(WindowMessage::Navigate(path));
+    }
+    Ok(messages)
+}
+
+fn send_messages(tx: &mpsc::Sender<WindowMessage>, messages: Vec<WindowMessage>) -> IpcResponse {
+    for message in messages {
+        if tx.send(message).is_err() {
+            return IpcResponse::error("Window is no longer running");
+        }
+    }
+    IpcResponse::ok()
+}
+
+/// Sends a single command to the running instance, and returns its response
+pub fn send_command(stream: &mut UnixStream, command: IpcCommand) -> Result<IpcResponse> {
+    let request = IpcRequest {
+        version: PROTOCOL_VERSION,
+        command,
+    };
+
+    // If the running instance is stuck, fail rather than leaving the launch hanging
+    stream.set_read_timeout(Some(HANDOFF_TIMEOUT))?;
+    stream.set_write_timeout(Some(HANDOFF_TIMEOUT))?;
+
+    let mut json = serde_json::to_string(&request)?;
+    json.push('\n');
+    stream.write_all(json.as_bytes())?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .map_err(|e| match e.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                anyhow!("The running instance is not responding")
+            }
+            _ => e.into(),
+        })?;
+    if line.is_empty() {
+        return Err(anyhow!("No response from running instance"));
+    }
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Hands the command to the instance holding the lock. As it may have only just taken the lock,
+/// we give it a little time to bind the socket before giving up.
+pub fn handle_active_instance(command: IpcCommand) -> ActiveInstance {
+    let socket_path = match get_socket_file_path() {
+        Ok(path) => path,
+        Err(e) => return ActiveInstance::Rejected(e.to_string()),
+    };
+    debug!("Attempting to Connect to Existing Socket at {socket_path:?}");
+
+    let started = Instant::now();
+    let mut stream = loop {
+        match UnixStream::connect(&socket_path) {
+            Ok(stream) => break stream,
+            Err(e) => {
+                if started.elapsed() > HANDOFF_TIMEOUT {
+                    debug!("Failed to Connect to Socket: {e}");
+                    return ActiveInstance::Rejected(
+                        "The running instance is not responding".to_string(),
+                    );
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+    };
+
+    debug!("Connected to Existing Socket, Sending {command:?}");
+    match send_command(&mut stream, command) {
+        Ok(response) if response.ok => ActiveInstance::Accepted,
+        Ok(response) => ActiveInstance::Rejected(
+            response
+                .error
+                .unwrap_or_else(|| "Unknown error".to_string()),
+        ),
+        Err(e) => ActiveInstance::Rejected(e.to_string()),
+    }
+}
+
+/// The token given to us by the launcher, used to let the running instance take focus on Wayland
+pub fn activation_token() -> Option<String> {
+    env::var("XDG_ACTIVATION_TOKEN")
+        .or_else(|_| env::var("DESKTOP_STARTUP_ID"))
+        .ok()
+        .filter(|token| !token.is_empty())
+}
+
+/// Returns our private runtime directory, creating it if needed. As anything in here is trusted,
+/// make sure it belongs to us and no one else can get into it.
+fn get_runtime_path() -> Result<PathBuf> {
+    let uid = current_uid();
+    let path = match runtime_dir() {
+        Some(dir) => dir.join(APP_NAME),
+
+        // The temp dir is shared between users, so make sure our path is unique to us
+        None => env::temp_dir().join(format!("{APP_NAME}-{uid}")),
+    };
+
+    if let Err(e) = DirBuilder::new().mode(0o700).create(&path)
+        && e.kind() != ErrorKind::AlreadyExists
+    {
+        bail!("Unable to create runtime directory {path:?}: {e}");
+    }
+
+    // Use symlink_metadata, so a symlink to somewhere else isn't followed
+    let metadata = fs::symlink_metadata(&path)?;
+    if !metadata.is_dir() {
+        bail!("Runtime path {path:?} is not a directory");
+    }
+    if metadata.uid() != uid {
+        bail!("Runtime directory {path:?} is owned by another user");
+    }
+    if metadata.mode() & 0o077 != 0 {
+        warn!("Runtime directory {path:?} is accessible by other users, fixing");
+        fs::set_permissions(&path, Permissions::from_mode(0o700))?;
+    }
+
+    Ok(path)
+}
+
+fn get_socket_file_path() -> Result<PathBuf> {
+    Ok(get_runtime_path()?.join("ipc.sock"))
+}
+
+fn get_lock_file_path() -> Result<PathBuf> {
+    Ok(get_runtime_path()?.join("instance.lock"))
+}
+
+fn current_uid() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+fn peer_uid(stream: &UnixStream) -> Result<u32> {
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut length = size_of::<libc::ucred>() as libc::socklen_t;
+
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&mut credentials as *mut libc::ucred).cast(),
+            &mut length,
+        )
+    };
+
+    if result != 0 {
+        bail!(
+            "Unable to read peer credentials: {}",
+            io::Error::last_os_error()
+        );
+    }
+    Ok(credentials.uid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // WindowMessage has nothing to compare with, so describe the ones we send
+    fn describe(message: &WindowMessage) -> String {
+        match message {
+            WindowMessage::Trigger => "trigger".into(),
+            WindowMessage::ActivationToken(token) => format!("token {token}"),
+            WindowMessage::Hide => "hide".into(),
+            WindowMessage::Toggle => "toggle".into(),
+            WindowMessage::Quit => "quit".into(),
+            WindowMessage::Reload => "reload".into(),
+            WindowMessage::Navigate(path) => format!("navigate {path}"),
+            WindowMessage::ShowMixer => "mixer".into(),
+            _ => "other".into(),
+        }
+    }
+
+    fn args(args: &[&str], token: Option<&str>) -> Vec<String> {
+        let argv: Vec<_> = std::iter::once(APP_NAME)
+            .chain(args.iter().copied())
+            .map(String::from)
+            .collect();
+        args_to_messages(&argv, token.map(String::from))
+            .unwrap()
+            .iter()
+            .map(describe)
+            .collect()
+    }
+
+    // Runs a client connection over a socket pair, returning a response for each line sent
+    fn exchange(input: &[u8]) -> (Vec<IpcResponse>, Vec<String>, Result<()>) {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let handler = thread::spawn(move || handle_client(server, &tx, &AppStatus::default()));
+
+        // The client may be dropped part way through
+        let _ = client.write_all(input);
+        let _ = client.shutdown(Shutdown::Write);
+        let responses = BufReader::new(&client)
+            .lines()
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect();
+
+        let result = handler.join().unwrap();
+        let messages = rx.try_iter().map(|message| describe(&message)).collect();
+        (responses, messages, result)
+    }
+
+    #[test]
+    fn parses_every_command() {
+        let parse = |json: &str| serde_json::from_str::<IpcRequest>(json).unwrap().command;
+
+        assert!(matches!(
+            parse(r#"{"version": 1, "command": "show"}"#),
+            IpcCommand::Show {
+                activation_token: None
+            }
+        ));
+        assert!(matches!(
+            parse(r#"{"version": 1, "command": "show", "activation_token": "abc"}"#),
+            IpcCommand::Show { activation_token: Some(token) } if token == "abc"
+        ));
+        assert!(matches!(
+            parse(r#"{"version": 1, "command": "hide"}"#),
+            IpcCommand::Hide
+        ));
+        assert!(matches!(
+            parse(r#"{"version": 1, "command": "toggle"}"#),
+            IpcCommand::Toggle {
+                activation_token: None
+            }
+        ));
+        assert!(matches!(
+            parse(r#"{"version": 1, "command": "quit"}"#),
+            IpcCommand::Quit
+        ));
+        assert!(matches!(
+            parse(r#"{"version": 1, "command": "reload"}"#),
+            IpcCommand::Reload
+        ));
+        assert!(matches!(
+            parse(r#"{"version": 1, "command": "navigate", "path": "/mixer"}"#),
+            IpcCommand::Navigate { path } if path == "/mixer"
+        ));
+        assert!(matches!(
+            parse(r#"{"version": 1, "command": "status"}"#),
+            IpcCommand::Status
+        ));
+        assert!(matches!(
+            parse(r#"{"version": 1, "command": "args", "argv": ["app", "--mixer"], "cwd": "/tmp"}"#),
+            IpcCommand::Args { argv, activation_token: None, .. } if argv.len() == 2
+        ));
+
+        assert!(
+            serde_json::from_str::<IpcRequest>(r#"{"version": 1, "command": "dance"}"#).is_err()
+        );
+        assert!(serde_json::from_str::<IpcRequest>(r#"{"command": "show"}"#).is_err());
+    }
+
+    #[test]
+    fn handles_each_line_in_turn() {
+        let input = concat!(
+            r#"{"version": 1, "command": "show", "activation_token": "abc"}"#,
+            "\n\n",
+            r#"{"version": 1, "command": "navigate", "path": "/mixer"}"#,
+            "\n",
+            r#"{"version": 1, "command": "status"}"#,
+            "\n",
+        );
+        let (responses, messages, result) = exchange(input.as_bytes());
+        result.unwrap();
+
+        assert_eq!(responses.len(), 3);
+        assert!(responses.iter().all(|response| response.ok));
+        assert_eq!(
+            responses[2].status.as_ref().unwrap().app_version,
+            env!("CARGO_PKG_VERSION")
+        );
+        assert_eq!(messages, vec!["token abc", "trigger", "navigate /mixer"]);
+    }
+
+    #[test]
+    fn rejects_newer_protocol_versions() {
+        let request = format!(
+            r#"{{"version": {}, "command": "quit"}}"#,
+            PROTOCOL_VERSION + 1
+        );
+        let (responses, messages, result) = exchange(format!("{request}\n").as_bytes());
+        result.unwrap();
+
+        assert!(!responses[0].ok);
+        assert!(responses[0].error.as_ref().unwrap().contains("version"));
+        assert!(messages.is_empty());
+    }
+
+    #[test]
+    fn reports_invalid_requests_and_carries_on() {
+        let input = concat!("not json\n", r#"{"version": 1, "command": "quit"}"#, "\n");
+        let (responses, messages, result) = exchange(input.as_bytes());
+        result.unwrap();
+
+        assert!(!responses[0].ok);
+        assert!(responses[1].ok);
+        assert_eq!(messages, vec!["quit"]);
+    }
+
+    #[test]
+    fn drops_clients_sending_oversized_messages() {
+        let mut input = vec![b' '; MAX_MESSAGE_SIZE as usize + 1];
+        input.extend_from_slice(b"\n{\"version\": 1, \"command\": \"quit\"}\n");
+        let (responses, messages, result) = exchange(&input);
+
+        assert!(result.is_err());
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].error.as_deref(), Some("Message too large"));
+        assert!(messages.is_empty());
+
+        // Right up to the limit is fine
+        let mut input = vec![b' '; MAX_MESSAGE_SIZE as usize - 1];
+        input.push(b'\n');
+        exchange(&input).2.unwrap();
+    }
+
+    #[test]
+    fn navigate_needs_an_absolute_path() {
+        assert_eq!(
+            describe(&navigate("/mixer".into()).unwrap()),
+            "navigate /mixer"
+        );
+        assert!(navigate("mixer".into()).is_err());
+        assert!(navigate("https://example.com/".into()).is_err());
+
+        let (responses, messages, _) =
+            exchange(b"{\"version\": 1, \"command\": \"navigate\", \"path\": \"mixer\"}\n");
+        assert!(!responses[0].ok);
+        assert!(messages.is_empty());
+    }
+
+    #[test]
+    fn args_show_the_window() {
+        assert_eq!(args(&[], None), vec!["trigger"]);
+        assert_eq!(args(&["--mixer"], None), vec!["mixer"]);
+        assert_eq!(args(&["--toggle"], None), vec!["toggle"]);
+        assert_eq!(args(&["--mixer", "--toggle"], None), vec!["mixer"]);
+    }
+
+    #[test]
+    fn args_hidden_does_nothing() {
+        assert!(args(&["--hidden"], None).is_empty());
+        assert!(args(&["--hidden"], Some("abc")).is_empty());
+    }
+
+    #[test]
+    fn args_navigate_after_showing() {
+        assert_eq!(
+            args(&["--page", "mixer"], None),
+            vec!["trigger", "navigate /mixer"]
+        );
+        assert_eq!(
+            args(&["--hidden", "--page", "/mixer"], None),
+            vec!["navigate /mixer"]
+        );
+    }
+
+    #[test]
+    fn args_activation_token_comes_first() {
+        assert_eq!(args(&[], Some("abc")), vec!["token abc", "trigger"]);
+        assert_eq!(
+            args(&["--toggle", "--page", "mixer"], Some("abc")),
+            vec!["token abc", "toggle", "navigate /mixer"]
+        );
+        assert_eq!(args(&["--mixer"], Some("abc")), vec!["token abc", "mixer"]);
+    }
+
+    #[test]
+    fn args_ignore_startup_options_but_reject_unknown_ones() {
+        assert_eq!(
+            args(&["--url", "http://localhost:1234"], None),
+            vec!["trigger"]
+        );
+
+        let argv = vec![APP_NAME.to_string(), "--dance".to_string()];
+        assert!(args_to_messages(&argv, None).is_err());
+    }
+
+    #[test]
+    fn send_command_reads_the_response() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let handler = thread::spawn(move || handle_client(server, &tx, &AppStatus::default()));
+
+        let response = send_command(&mut client, IpcCommand::Reload).unwrap();
+        assert!(response.ok);
+        assert_eq!(response.version, PROTOCOL_VERSION);
+        handler.join().unwrap().unwrap();
+        assert!(matches!(rx.try_recv(), Ok(WindowMessage::Reload)));
+    }
+
+    #[test]
+    fn send_command_times_out() {
+        // Nobody ever answers on the other end
+        let (mut client, _server) = UnixStream::pair().unwrap();
+        let started = Instant::now();
+        let e = send_command(&mut client, IpcCommand::Reload).unwrap_err();
+        assert_eq!(e.to_string(), "The running instance is not responding");
+        assert!(started.elapsed() >= HANDOFF_TIMEOUT);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::config::{DaemonAddress, LaunchConfig};
+use crate::daemon_api::{ApiError, DaemonRequest, http_request};
+use anyhow::{Result, bail};
+use log::{debug, info, warn};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use zbus::blocking::Connection;
+use zbus::proxy;
+use zbus::zvariant::OwnedObjectPath;
+
+#[proxy(
+    interface = "org.freedesktop.systemd1.Manager",
+    default_service = "org.freedesktop.systemd1",
+    default_path = "/org/freedesktop/systemd1"
+)]
+trait SystemdManager {
+    fn start_unit(&self, name: &str, mode: &str) -> zbus::Result<OwnedObjectPath>;
+}
+
+/// A quick check to see whether the daemon is answering on its port
+pub fn is_listening(address: &DaemonAddress) -> bool {
+    let result = http_request(address, &DaemonRequest::Ping, Duration::from_millis(500));
+
+    // Any response at all means something is there, even if it didn't like the request
+    match result {
+        Ok(_) => true,
+        Err(ApiError::Daemon(_) | ApiError::Http(_) | ApiError::Protocol(_)) => true,
+        Err(ApiError::Disconnected | ApiError::Timeout | ApiError::Io(_)) => false,
+    }
+}
+
+/// Attempts to start the Pipeweaver daemon, first via the systemd user manager, then by
+/// spawning the binary directly. This doesn't wait for the daemon to become available.
+pub fn start_daemon(config: &LaunchConfig) -> Result<()> {
+    match start_systemd_unit(&config.unit) {
+        Ok(()) => return Ok(()),
+        Err(e) => warn!("Unable to start {} via systemd: {e}", config.unit),
+    }
+
+    match spawn_binary(&config.binary) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            warn!("Unable to spawn {}: {e}", config.binary);
+            bail!("Unable to start Pipeweaver: {e}");
+        }
+    }
+}
+
+fn start_systemd_unit(unit: &str) -> Result<()> {
+    debug!("Requesting systemd start {unit}");
+    let connection = Connection::session()?;
+    let manager = SystemdManagerProxyBlocking::new(&connection)?;
+    let job = manager.start_unit(unit, "replace")?;
+
+    info!("Started {unit} via systemd (job {})", job.as_str());
+    Ok(())
+}
+
+fn spawn_binary(binary: &str) -> Result<()> {
+    debug!("Spawning {binary}");
+
+    // Put the daemon in its own process group so it isn't taken down with us by a terminal
+    let mut child = Command::new(binary)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()?;
+
+    info!("Spawned {binary} (pid {})", child.id());
+
+    // Reap the process if it exits while we're still running
+    thread::spawn(move || {
+        if let Ok(status) = child.wait() {
+            debug!("Pipeweaver process exited: {status}");
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::WaitPolicy;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use std::time::Instant;
+
+    fn config(binary: &str) -> LaunchConfig {
+        LaunchConfig {
+            automatic: true,
+            unit: "pipeweaver-app-test-missing.service".into(),
+            binary: binary.into(),
+            wait: WaitPolicy {
+                interval: Duration::from_millis(50),
+                timeout: Duration::from_secs(1),
+            },
+        }
+    }
+
+    // A stand-in for the daemon, which leaves a file next to itself to show that it ran
+    fn fake_binary() -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("pipeweaver-launcher-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let binary = dir.join("pipeweaver");
+        fs::write(&binary, "#!/bin/sh\ntouch \"$0.ran\"\n").unwrap();
+        fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+        (binary.clone(), binary.with_extension("ran"))
+    }
+
+    fn address(listener: &TcpListener) -> DaemonAddress {
+        DaemonAddress {
+            host: "127.0.0.1".into(),
+            port: listener.local_addr().unwrap().port(),
+            path: String::new(),
+        }
+    }
+
+    #[test]
+    fn falls_back_to_spawning_the_binary() {
+        let (binary, marker) = fake_binary();
+        start_daemon(&config(binary.to_str().unwrap())).unwrap();
+
+        let started = Instant::now();
+        while !marker.exists() {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "binary never ran"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        fs::remove_dir_all(binary.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn fails_without_a_unit_or_binary() {
+        assert!(start_daemon(&config("/nonexistent/pipeweaver")).is_err());
+    }
+
+    #[test]
+    fn any_http_response_is_listening() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = address(&listener);
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.read(&mut [0; 1024]);
+            let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        });
+        assert!(is_listening(&address));
+    }
+
+    #[test]
+    fn nothing_on_the_port_is_not_listening() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = address(&listener);
+        drop(listener);
+        assert!(!is_listening(&address));
+    }
+}
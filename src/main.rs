@@ -1,25 +1,52 @@
-use anyhow::{Result, anyhow, bail};
+use anyhow::{Result, bail};
+use clap::Parser;
 use cpp::cpp;
-use dirs::runtime_dir;
 use log::{debug, error, info, warn};
-use qmetaobject::QObjectPinned;
 use qmetaobject::prelude::*;
 use qmetaobject::webengine;
+use qmetaobject::{QObjectPinned, queued_callback};
 use std::cell::RefCell;
-use std::io::{ErrorKind, Read, Write};
-use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::PathBuf;
 use std::rc::Rc;
-use std::sync::mpsc;
-use std::time::Duration;
-use std::{env, fs, thread};
-use tungstenite::http::Uri;
-use tungstenite::{Message, connect};
-
+use std::sync::{Arc, mpsc};
+use std::{env, process, thread};
+
+mod autostart;
+mod config;
+mod daemon_api;
+mod daemon_model;
+mod daemon_state;
+mod dbus;
+mod ipc;
+mod launcher;
+mod mixer;
+mod notifications;
+mod osd;
+mod screens;
+mod settings;
+mod settings_file;
+mod shortcuts;
+mod tray;
+mod websocket;
 mod window_handler;
 mod window_properties;
 
-use crate::window_handler::{WindowHandler, WindowMessage};
+use crate::config::{AutostartAction, CHROMIUM_FLAGS_ENV, Cli, Config};
+use crate::daemon_api::DaemonClient;
+use crate::daemon_model::DaemonModel;
+use crate::dbus::DbusService;
+use crate::ipc::{
+    ActiveInstance, InstanceLock, IpcCommand, activation_token, bind_socket,
+    handle_active_instance, ipc_thread_main,
+};
+use crate::mixer::MixerProperties;
+use crate::notifications::{DaemonEvent, notifications_thread_main};
+use crate::osd::OsdProperties;
+use crate::settings::AppSettings;
+use crate::settings_file::Settings;
+use crate::shortcuts::shortcuts_thread_main;
+use crate::tray::TrayHandle;
+use crate::websocket::websocket_main_thread;
+use crate::window_handler::{AppStatus, WindowHandler, WindowMessage};
 use window_properties::WindowProperties;
 
 const APP_NAME: &str = "pipeweaver-app";
@@ -33,6 +60,8 @@ cpp! {{
 qrc!(pipeweaver_resources,
     "webengine" {
         "main.qml",
+        "osd.qml",
+        "mixer.qml",
         "resources/pipeweaver.svg",
     },
 );
@@ -47,44 +76,127 @@ fn main() -> Result<()> {
 }
 
 fn real_main() -> Result<()> {
+    let cli = Cli::parse();
+
+    // Used by provisioning scripts, this doesn't need (or want) the rest of the app
+    if let Some(action) = cli.autostart {
+        env_logger::init();
+        autostart::set_enabled(action == AutostartAction::Enable)?;
+        return Ok(());
+    }
+
+    let mut config = Config::load(&cli)?;
+    if cli.print_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+
     unsafe {
         //env::set_var("QT_QPA_PLATFORM", "xcb");
-        env::set_var("RUST_LOG", "debug");
-        env::set_var(
-            "QTWEBENGINE_CHROMIUM_FLAGS",
-            "  --enable-features=Canvas2DImageChromium \
-                     --enable-gpu-memory-buffer-compositor-resources \
-                     --enable-zero-copy \
-                     --force-gpu-mem-available-mb=256 \
-                     --max-decoded-image-size-mb=64 \
-                     --js-flags=--expose-gc,--max-old-space-size=128 \
-                     --disable-software-rasterizer \
-                     --disable-dev-shm-usage \
-                     --disable-gpu-shader-disk-cache \
-                     --num-raster-threads=2 \
-                     --single-process",
-        );
+        env::set_var(CHROMIUM_FLAGS_ENV, &config.webengine.chromium_flags);
     }
-    env_logger::init();
+    env_logger::Builder::new()
+        .parse_filters(&config.log_level)
+        .init();
 
-    if handle_active_instance() {
-        println!("Instance Already active, Exiting");
-        return Ok(());
+    match &config.file {
+        Some(path) => debug!("Loaded config from {path:?}"),
+        None => debug!("No config file, using the defaults"),
+    }
+    debug!("Using Pipeweaver at {}", config.daemon.base_url());
+
+    // Whoever holds this lock is the running instance, if it's not us, pass our command line
+    // to it so it can act on it.
+    let Some(_instance_lock) = InstanceLock::acquire()? else {
+        let command = IpcCommand::Args {
+            argv: env::args().collect(),
+            cwd: env::current_dir().unwrap_or_default(),
+            activation_token: activation_token(),
+        };
+        match handle_active_instance(command) {
+            ActiveInstance::Accepted => {
+                println!("Instance Already active, Exiting");
+                return Ok(());
+            }
+            ActiveInstance::Rejected(e) => {
+                eprintln!("Running instance rejected the request: {e}");
+                process::exit(1);
+            }
+        }
+    };
+
+    // Bind the socket straight away, so any other launches can reach us while we start up
+    let listener = bind_socket()?;
+
+    // If we've been told to, start the daemon before trying to connect
+    if config.launch.automatic && !launcher::is_listening(&config.daemon) {
+        launch_daemon(&mut config)?;
     }
 
     // Channel for notifications from code to the Window
     let (notify_tx, notify_rx) = mpsc::channel();
 
-    // Ok, lets try getting the websocket running
-    let (res_tx, res_rx) = mpsc::channel();
-    let notify_websocket = notify_tx.clone();
+    // State shared between the window and the background threads
+    let status = Arc::new(AppStatus::default());
+
+    // Spawn the IPC thread with only the sender (thread must NOT touch QObjects)
+    let ipc_status = status.clone();
+    let ipc_tx = notify_tx.clone();
     thread::spawn(move || {
-        websocket_main_thread(res_tx, notify_websocket);
+        if let Err(e) = ipc_thread_main(listener, ipc_tx, ipc_status) {
+            warn!("IPC thread exited with error: {e}");
+        }
     });
 
-    if let Err(e) = res_rx.recv()? {
-        error!("Failed to Connect to Pipeweaver: {e}");
-        bail!("Cannot Start, Pipeweaver is not running.   ");
+    // Notifications are opt-in, if nothing's enabled the events are simply dropped
+    let (event_tx, event_rx) = mpsc::channel();
+    if config.notifications.any() {
+        let notification_config = config.notifications;
+        let notification_tx = notify_tx.clone();
+        thread::spawn(move || {
+            if let Err(e) =
+                notifications_thread_main(notification_config, event_rx, notification_tx)
+            {
+                warn!("Unable to show notifications: {e:#}");
+            }
+        });
+    } else {
+        drop(event_rx);
+    }
+
+    // Ok, lets try getting the websocket running
+    let (res_rx, client) = loop {
+        let (res_rx, client) = spawn_websocket(&config, notify_tx.clone(), event_tx.clone())?;
+
+        // If we're waiting for the daemon, the window will show a loading screen until it connects
+        if config.wait.is_some() {
+            break (res_rx, client);
+        }
+
+        match res_rx.recv()? {
+            Ok(()) => break (res_rx, client),
+            Err(e) => {
+                error!("Failed to Connect to Pipeweaver: {e}");
+                match prompt_not_running() {
+                    Some(StartChoice::Start) => launch_daemon(&mut config)?,
+                    Some(StartChoice::Retry) => {}
+                    Some(StartChoice::Quit) => return Ok(()),
+                    None => bail!("Cannot Start, Pipeweaver is not running.   "),
+                }
+            }
+        }
+    };
+
+    // Global shortcuts are handled by the desktop, we just get told when they're pressed
+    if !config.shortcuts.is_empty() {
+        let shortcuts = config.shortcuts.clone();
+        let shortcut_tx = notify_tx.clone();
+        let shortcut_client = client.clone();
+        thread::spawn(move || {
+            if let Err(e) = shortcuts_thread_main(shortcuts, shortcut_tx, shortcut_client) {
+                warn!("Unable to register global shortcuts: {e:#}");
+            }
+        });
     }
 
     webengine::initialize();
@@ -98,18 +210,55 @@ fn real_main() -> Result<()> {
         });
     }
 
-    // Spawn the IPC thread with only the sender (thread must NOT touch QObjects)
-    thread::spawn(move || {
-        if let Err(e) = ipc_thread_main(notify_tx) {
-            warn!("IPC thread exited with error: {e}");
-        }
-    });
-
     // Create the engine and link up the rust side
     let mut engine = QmlEngine::new();
 
-    let window_props = Rc::new(RefCell::new(WindowProperties::new()));
-    let ipc_handler = Rc::new(RefCell::new(WindowHandler::new(notify_rx)));
+    // Window positions and preferences, shared by everything that saves into the settings file
+    let mut settings = Settings::load();
+    if cli.reset_geometry {
+        info!("Resetting the window and mixer geometry");
+        settings.reset_geometry();
+    }
+    let settings = Rc::new(RefCell::new(settings));
+
+    // The tray is optional, if there's no StatusNotifierWatcher we simply go without
+    let tray = if config.tray {
+        let close_to_tray = settings.borrow().preferences.close_to_tray;
+        TrayHandle::spawn(notify_tx.clone(), close_to_tray)
+            .inspect_err(|e| warn!("Unable to create tray icon: {e}"))
+            .ok()
+    } else {
+        None
+    };
+
+    let window_props = Rc::new(RefCell::new(WindowProperties::new(
+        &config,
+        settings.clone(),
+        cli.page_path(),
+        tray.is_some(),
+    )));
+
+    // Like the tray, the D-Bus service is a nice to have, the socket is always available
+    let dbus = DbusService::spawn(notify_tx.clone(), status.clone())
+        .inspect_err(|e| warn!("Unable to register on the session bus: {e}"))
+        .ok();
+
+    let daemon_model = Rc::new(RefCell::new(DaemonModel::default()));
+    let mixer_props = Rc::new(RefCell::new(MixerProperties::new(
+        &config,
+        settings,
+        cli.mixer,
+        status.clone(),
+        client,
+    )));
+    let ipc_handler = Rc::new(RefCell::new(WindowHandler::new(
+        status,
+        tray,
+        dbus,
+        daemon_model.clone(),
+    )));
+    let app_settings = Rc::new(RefCell::new(AppSettings::new()));
+    let osd_props = Rc::new(RefCell::new(OsdProperties::new(&config.osd)));
     unsafe {
         engine.set_object_property(
             "windowProperties".into(),
@@ -120,159 +269,90 @@ fn real_main() -> Result<()> {
             "windowHandler".into(),
             QObjectPinned::new(ipc_handler.as_ref()),
         );
-    }
 
-    engine.load_file("qrc:/webengine/main.qml".into());
-    engine.exec();
-
-    Ok(())
-}
-
-fn websocket_main_thread(res: mpsc::Sender<Result<()>>, tx: mpsc::Sender<WindowMessage>) {
-    // We need to spawn up a Websocket connection, then simply read from it until closed
-    let uri = match Uri::builder()
-        .authority("localhost:14565")
-        .scheme("ws")
-        .path_and_query("/api/websocket")
-        .build()
-    {
-        Ok(uri) => uri,
-        Err(e) => {
-            let _ = res.send(Err(anyhow!(e)));
-            return;
-        }
-    };
+        engine.set_object_property(
+            "daemonState".into(),
+            QObjectPinned::new(daemon_model.as_ref()),
+        );
 
-    info!("Attempting to connect to Pipeweaver at {uri}");
-    let (mut socket, response) = match connect(uri) {
-        Ok((socket, response)) => (socket, response),
-        Err(e) => {
-            let _ = res.send(Err(anyhow!(e)));
-            return;
-        }
-    };
+        engine.set_object_property(
+            "appSettings".into(),
+            QObjectPinned::new(app_settings.as_ref()),
+        );
 
-    info!("Connected, HTTP status: {}", response.status());
-    let _ = res.send(Ok(()));
-
-    loop {
-        match socket.read() {
-            Ok(msg) => {
-                // NOOP everything except Ping/Pong
-                match msg {
-                    Message::Ping(payload) => {
-                        let _ = socket.send(Message::Pong(payload));
-                    }
-                    Message::Close(_) => {
-                        println!("Server closed the connection");
-                        break;
-                    }
-                    _ => {}
-                }
-            }
-            Err(tungstenite::Error::ConnectionClosed) => {
-                error!("Disconnected: connection closed");
-                break;
-            }
-            Err(tungstenite::Error::Protocol(e)) => {
-                error!("Disconnected: protocol error: {e}");
-                break;
-            }
-            Err(e) => {
-                error!("Disconnected: other error: {e}");
+        engine.set_object_property(
+            "osdProperties".into(),
+            QObjectPinned::new(osd_props.as_ref()),
+        );
 
-                break;
-            }
-        }
+        engine.set_object_property(
+            "mixerProperties".into(),
+            QObjectPinned::new(mixer_props.as_ref()),
+        );
     }
 
-    // If we get here, the connection has been dropped, close our window.
-    info!("Connection to Pipeweaver Lost, sending Close");
-    let _ = tx.send(WindowMessage::Close);
-}
+    // Deliver messages from the background threads onto the Qt event loop as they arrive, rather
+    // than having Qt poll for them.
+    let handler = ipc_handler.clone();
+    let deliver = queued_callback(move |msg: WindowMessage| handler.borrow().handle_message(msg));
+    thread::spawn(move || {
+        for msg in notify_rx {
+            deliver(msg);
+        }
+    });
 
-fn ipc_thread_main(tx: mpsc::Sender<WindowMessage>) -> Result<()> {
-    debug!("Spawning IPC Socket Handler");
+    engine.load_file("qrc:/webengine/main.qml".into());
+    engine.load_file("qrc:/webengine/mixer.qml".into());
 
-    let socket_path = get_socket_file_path();
-    if let Some(parent) = socket_path.parent()
-        && let Err(e) = fs::create_dir_all(parent)
-    {
-        warn!("Failed to create socket directory {parent:?}: {e}");
-        bail!("Failed to Open IPC Socket");
+    // The OSD is its own top level window, so it can appear while the main window is hidden
+    if config.osd.enabled {
+        engine.load_file("qrc:/webengine/osd.qml".into());
     }
 
-    if socket_path.exists() {
-        let _ = fs::remove_file(&socket_path);
-    }
+    engine.exec();
 
-    let listener = match UnixListener::bind(&socket_path) {
-        Ok(listener) => listener,
-        Err(e) => {
-            warn!("Failed to bind to socket: {e}");
-            bail!("Failed to bind to socket: {e}");
-        }
-    };
+    // The mixer can be open (or not) regardless of how the app exits, so save it here
+    mixer_props.borrow().save_geometry();
 
-    listener.set_nonblocking(true)?;
-
-    debug!("IPC listener started at {socket_path:?}");
-    loop {
-        match listener.accept() {
-            Ok((mut stream, _)) => {
-                let mut msg = String::new();
-                if let Err(e) = stream.read_to_string(&mut msg) {
-                    warn!("Failed to read message from stream: {e}");
-                } else if msg == "TRIGGER" {
-                    let _ = tx.send(WindowMessage::Trigger);
-                }
-            }
-            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
-                std::thread::sleep(Duration::from_millis(100));
-            }
-            Err(e) => {
-                warn!("Unexpected socket error: {e}");
-                break;
-            }
-        }
+    // If we gave up waiting for the daemon, the window will have been closed, report why.
+    if let Ok(result) = res_rx.try_recv() {
+        check_connection(result)?;
     }
-    let _ = fs::remove_file(&socket_path);
-    debug!("IPC Socket closed (thread)");
+
     Ok(())
 }
 
-pub fn handle_active_instance() -> bool {
-    let socket_path = get_socket_file_path();
-    debug!("Looking for Socket at {socket_path:?}");
+// Returns the receiver for the initial connection result, and a client for talking to the daemon
+fn spawn_websocket(
+    config: &Config,
+    tx: mpsc::Sender<WindowMessage>,
+    events: mpsc::Sender<DaemonEvent>,
+) -> Result<(mpsc::Receiver<Result<()>>, DaemonClient)> {
+    let (res_tx, res_rx) = mpsc::channel();
+    let (client, requests) = DaemonClient::new()?;
+    let uri = config.daemon.websocket_uri()?;
+    let policy = config.reconnect;
+    let wait = config.wait;
+    thread::spawn(move || {
+        websocket_main_thread(uri, res_tx, tx, requests, events, policy, wait);
+    });
+    Ok((res_rx, client))
+}
 
-    if !socket_path.exists() {
-        debug!("Existing socket is not present");
-        // The socket file doesn't exist, so the socket can't exist.
-        return false;
-    }
+fn launch_daemon(config: &mut Config) -> Result<()> {
+    launcher::start_daemon(&config.launch)?;
 
-    debug!("Attempting to Connect to Existing Socket");
-    // The socket exists, let's see if we can connect to it
-    match UnixStream::connect(&socket_path) {
-        Ok(mut stream) => {
-            debug!("Connected to Existing Socket at {socket_path:?}, Sending Trigger");
-            let _ = stream.write_all(b"TRIGGER");
-            return true;
-        }
-        Err(e) => {
-            debug!("Failed to Connect to Socket: {e}");
-            debug!("Removing Stale Socket File");
-            let _ = fs::remove_file(socket_path);
-        }
-    }
-    false
+    // Give the daemon some time to start up, the window will show as loading until it's ready
+    config.wait = Some(config.launch.wait);
+    Ok(())
 }
 
-fn get_socket_file_path() -> PathBuf {
-    let mut path = runtime_dir().unwrap_or_else(env::temp_dir);
-    path.push(format!("{}.sock", APP_NAME));
-
-    path
+fn check_connection(result: Result<()>) -> Result<()> {
+    if let Err(e) = result {
+        error!("Failed to Connect to Pipeweaver: {e}");
+        bail!("Cannot Start, Pipeweaver is not running.   ");
+    }
+    Ok(())
 }
 
 pub fn display_error(message: String) {
@@ -295,3 +375,62 @@ pub fn display_error(message: String) {
             .output();
     }
 }
+
+enum StartChoice {
+    Start,
+    Retry,
+    Quit,
+}
+
+/// Asks the user what to do when Pipeweaver isn't running, returns None if we were unable to ask
+fn prompt_not_running() -> Option<StartChoice> {
+    use std::process::Command;
+    let message = "Pipeweaver is not running.";
+
+    match Command::new("kdialog")
+        .arg("--title")
+        .arg("Pipeweaver UI")
+        .arg("--warningyesnocancel")
+        .arg(message)
+        .arg("--yes-label")
+        .arg("Start Pipeweaver")
+        .arg("--no-label")
+        .arg("Retry")
+        .arg("--cancel-label")
+        .arg("Quit")
+        .status()
+    {
+        Ok(status) => {
+            return match status.code() {
+                Some(0) => Some(StartChoice::Start),
+                Some(1) => Some(StartChoice::Retry),
+                _ => Some(StartChoice::Quit),
+            };
+        }
+        Err(e) => println!("Error Running kdialog: {e}, falling back to zenity.."),
+    }
+
+    // Zenity only has two buttons, so Retry comes through as an 'extra' button on stdout
+    let output = Command::new("zenity")
+        .arg("--title")
+        .arg("Pipeweaver UI")
+        .arg("--question")
+        .arg("--text")
+        .arg(message)
+        .arg("--ok-label")
+        .arg("Start Pipeweaver")
+        .arg("--cancel-label")
+        .arg("Quit")
+        .arg("--extra-button")
+        .arg("Retry")
+        .output()
+        .ok()?;
+
+    if output.status.success() {
+        Some(StartChoice::Start)
+    } else if String::from_utf8_lossy(&output.stdout).trim() == "Retry" {
+        Some(StartChoice::Retry)
+    } else {
+        Some(StartChoice::Quit)
+    }
+}
@@ -0,0 +1,198 @@
+use crate::config::Config;
+use crate::daemon_api::{DaemonClient, DaemonRequest, Mix, MuteState, MuteTarget, PipewireCommand};
+use crate::daemon_state::{Channel, ChannelKind};
+use crate::screens::{self, Rect};
+use crate::settings_file::{Placement, Settings, WindowState};
+use crate::window_handler::AppStatus;
+use log::{debug, warn};
+use qmetaobject::prelude::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Backs the mini mixer window, which shows the daemon's channels without needing the web UI
+#[derive(Default, QObject)]
+pub struct MixerProperties {
+    base: qt_base_class!(trait QObject),
+
+    width: qt_property!(i32; NOTIFY width_changed),
+    height: qt_property!(i32; NOTIFY height_changed),
+    x: qt_property!(i32; NOTIFY x_changed),
+    y: qt_property!(i32; NOTIFY y_changed),
+
+    width_changed: qt_signal!(),
+    height_changed: qt_signal!(),
+    x_changed: qt_signal!(),
+    y_changed: qt_signal!(),
+
+    // Whether the mixer's position can be set and saved, which isn't the case on Wayland
+    can_position: qt_property!(bool; NOTIFY can_position_changed),
+    can_position_changed: qt_signal!(),
+
+    // Tracks whether the mixer is open, so it can be reopened next time
+    visible: qt_property!(bool; NOTIFY visible_changed),
+    visible_changed: qt_signal!(),
+
+    always_on_top: qt_property!(bool; NOTIFY always_on_top_changed),
+    always_on_top_changed: qt_signal!(),
+
+    // Called from QML when the user changes a channel
+    set_volume: qt_method!(fn(&self, id: QString, volume: i32)),
+    set_muted: qt_method!(fn(&self, id: QString, muted: bool)),
+
+    status: Arc<AppStatus>,
+    client: Option<DaemonClient>,
+
+    // The Qt platform plugin in use, the saved geometry is kept separately for each
+    platform: String,
+    settings: Rc<RefCell<Settings>>,
+}
+
+impl MixerProperties {
+    fn load_placement(settings: &Settings, platform: &str) -> Placement {
+        let saved = settings
+            .mixer
+            .as_ref()
+            .and_then(|mixer| mixer.platforms.get(platform).or(mixer.fallback.as_ref()));
+        if let Some(placement) = saved {
+            debug!(
+                "Loaded {platform} mixer geometry: {}x{} at ({:?}, {:?})",
+                placement.width, placement.height, placement.x, placement.y
+            );
+            return placement.clone();
+        }
+
+        // Tall and narrow, to sit at the side of a screen
+        Placement {
+            width: 360,
+            height: 480,
+            ..Default::default()
+        }
+    }
+
+    pub fn new(
+        config: &Config,
+        settings: Rc<RefCell<Settings>>,
+        open: bool,
+        status: Arc<AppStatus>,
+        client: DaemonClient,
+    ) -> Self {
+        let platform = screens::platform_name();
+        let can_position = screens::can_position_windows(&platform);
+        let placement = Self::load_placement(&settings.borrow(), &platform);
+        let placed = screens::restore(&placement, can_position, &screens::screens());
+
+        let was_open = settings
+            .borrow()
+            .mixer
+            .as_ref()
+            .is_some_and(|mixer| mixer.visible);
+        MixerProperties {
+            width: placed.width,
+            height: placed.height,
+            x: placed.x,
+            y: placed.y,
+            can_position,
+            visible: open || was_open,
+            always_on_top: config.mixer.always_on_top,
+            status,
+            client: Some(client),
+            platform,
+            settings,
+            ..Default::default()
+        }
+    }
+
+    pub fn save_geometry(&self) {
+        let window = Rect {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+        };
+        let placement = screens::placement(
+            window,
+            WindowState::Normal,
+            self.can_position,
+            &screens::screens(),
+        );
+
+        debug!(
+            "Saving {} mixer geometry: {}x{} at ({:?}, {:?})",
+            self.platform, placement.width, placement.height, placement.x, placement.y
+        );
+
+        let mut settings = self.settings.borrow_mut();
+        let mixer = settings.mixer.get_or_insert_default();
+        mixer.platforms.insert(self.platform.clone(), placement);
+        mixer.visible = self.visible;
+        settings.save();
+    }
+
+    pub fn set_volume(&self, id: QString, volume: i32) {
+        let volume = u8::try_from(volume.clamp(0, 100)).unwrap_or_default();
+        if let Some(channel) = self.find_channel(&id.to_string()) {
+            self.send(vec![volume_command(&channel, volume)]);
+        }
+    }
+
+    pub fn set_muted(&self, id: QString, muted: bool) {
+        if let Some(channel) = self.find_channel(&id.to_string()) {
+            self.send(mute_commands(&channel, muted));
+        }
+    }
+
+    fn find_channel(&self, id: &str) -> Option<Channel> {
+        let daemon = self.status.daemon.lock().ok()?;
+        let channel = daemon.channels.iter().find(|channel| channel.id == id);
+        if channel.is_none() {
+            warn!("Mixer changed unknown channel {id}");
+        }
+        channel.cloned()
+    }
+
+    fn send(&self, commands: Vec<PipewireCommand>) {
+        let Some(client) = &self.client else {
+            return;
+        };
+        for command in commands {
+            if let Err(e) = client.send(DaemonRequest::Pipewire(command)) {
+                warn!("Unable to send mixer change: {e}");
+            }
+        }
+    }
+}
+
+// Sources have a volume per mix, the mixer only controls the first (the one we report)
+fn volume_command(channel: &Channel, volume: u8) -> PipewireCommand {
+    let id = channel.id.clone();
+    match channel.kind {
+        ChannelKind::Source => PipewireCommand::SetSourceVolume(id, Mix::A, volume),
+        ChannelKind::Target => PipewireCommand::SetTargetVolume(id, volume),
+    }
+}
+
+// Sources can be muted to each mix separately, unmuting clears all of them
+fn mute_commands(channel: &Channel, muted: bool) -> Vec<PipewireCommand> {
+    let id = channel.id.clone();
+    match channel.kind {
+        ChannelKind::Source if muted => {
+            vec![PipewireCommand::AddSourceMuteTarget(
+                id,
+                MuteTarget::TargetA,
+            )]
+        }
+        ChannelKind::Source => [MuteTarget::TargetA, MuteTarget::TargetB]
+            .into_iter()
+            .map(|target| PipewireCommand::DelSourceMuteTarget(id.clone(), target))
+            .collect(),
+        ChannelKind::Target => {
+            let state = if muted {
+                MuteState::Muted
+            } else {
+                MuteState::Unmuted
+            };
+            vec![PipewireCommand::SetTargetMuteState(id, state)]
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use crate::APP_NAME;
+use crate::config::NotificationConfig;
+use crate::daemon_state::{DaemonState, Device};
+use crate::window_handler::WindowMessage;
+use anyhow::Result;
+use log::{debug, warn};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use zbus::blocking::Connection;
+use zbus::proxy;
+use zbus::zvariant::Value;
+
+// The action used when the notification itself is clicked
+const DEFAULT_ACTION: &str = "default";
+
+#[proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+/// Things happening to the daemon which may be worth telling the user about
+#[derive(Debug)]
+pub enum DaemonEvent {
+    ConnectionLost,
+    ConnectionRestored,
+    State(DaemonState),
+}
+
+struct Notification {
+    summary: String,
+    body: String,
+
+    // Connection notifications replace each other, so only the latest is shown
+    replaces_connection: bool,
+}
+
+/// Turns daemon events into desktop notifications, based on what the user has opted in to
+pub fn notifications_thread_main(
+    config: NotificationConfig,
+    events: mpsc::Receiver<DaemonEvent>,
+    tx: mpsc::Sender<WindowMessage>,
+) -> Result<()> {
+    let connection = Connection::session()?;
+    let proxy = NotificationsProxyBlocking::new(&connection)?;
+
+    // Signals from the server are broadcast, so we need to know which notifications are ours
+    let ours = Arc::new(Mutex::new(HashSet::new()));
+    let signals = proxy.inner().receive_all_signals()?;
+    let signal_ours = ours.clone();
+    thread::spawn(move || {
+        for signal in signals {
+            handle_signal(&signal, &signal_ours, &tx);
+        }
+    });
+
+    let mut previous: Option<DaemonState> = None;
+    let mut connection_id = 0;
+
+    for event in events {
+        let notifications = match event {
+            DaemonEvent::ConnectionLost if config.connection => vec![Notification {
+                summary: "Connection to Pipeweaver lost".into(),
+                body: "Attempting to reconnect…".into(),
+                replaces_connection: true,
+            }],
+            DaemonEvent::ConnectionRestored if config.connection => vec![Notification {
+                summary: "Reconnected to Pipeweaver".into(),
+                body: String::new(),
+                replaces_connection: true,
+            }],
+            DaemonEvent::State(state) => {
+                // Nothing to compare the first state to, so just remember it
+                let notifications = previous
+                    .as_ref()
+                    .map(|previous| state_changes(&config, previous, &state))
+                    .unwrap_or_default();
+                previous = Some(state);
+                notifications
+            }
+            _ => vec![],
+        };
+
+        for notification in notifications {
+            let replaces_id = if notification.replaces_connection {
+                connection_id
+            } else {
+                0
+            };
+
+            let hints = HashMap::from([("desktop-entry", Value::from(APP_NAME))]);
+            match proxy.notify(
+                "Pipeweaver",
+                replaces_id,
+                "pipeweaver",
+                &notification.summary,
+                &notification.body,
+                &[DEFAULT_ACTION, "Open Pipeweaver"],
+                hints,
+                -1,
+            ) {
+                Ok(id) => {
+                    debug!("Sent notification {id}: {}", notification.summary);
+                    if notification.replaces_connection {
+                        connection_id = id;
+                    }
+                    if let Ok(mut ours) = ours.lock() {
+                        ours.insert(id);
+                    }
+                }
+                Err(e) => warn!("Unable to send notification: {e}"),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn state_changes(
+    config: &NotificationConfig,
+    previous: &DaemonState,
+    current: &DaemonState,
+) -> Vec<Notification> {
+    let mut notifications = vec![];
+
+    // A new profile brings its own channels, but the hardware hasn't changed
+    let profile_changed = previous.profile != current.profile;
+    if config.devices && !profile_changed {
+        let ids = |state: &DaemonState| -> HashSet<String> {
+            state
+                .devices
+                .iter()
+                .map(|device| device.id.clone())
+                .collect()
+        };
+        let (before, after) = (ids(previous), ids(current));
+
+        for Device { id, name, .. } in &current.devices {
+            if !before.contains(id) {
+                notifications.push(Notification {
+                    summary: "Device connected".into(),
+                    body: name.clone(),
+                    replaces_connection: false,
+                });
+            }
+        }
+        for Device { id, name, .. } in &previous.devices {
+            if !after.contains(id) {
+                notifications.push(Notification {
+                    summary: "Device disconnected".into(),
+                    body: name.clone(),
+                    replaces_connection: false,
+                });
+            }
+        }
+    }
+
+    if config.profile
+        && profile_changed
+        && let Some(profile) = &current.profile
+    {
+        notifications.push(Notification {
+            summary: "Profile changed".into(),
+            body: profile.clone(),
+            replaces_connection: false,
+        });
+    }
+
+    notifications
+}
+
+fn handle_signal(
+    signal: &zbus::Message,
+    ours: &Mutex<HashSet<u32>>,
+    tx: &mpsc::Sender<WindowMessage>,
+) {
+    let header = signal.header();
+    let Some(member) = header.member() else {
+        return;
+    };
+    let Ok(mut ours) = ours.lock() else {
+        return;
+    };
+
+    match member.as_str() {
+        // Sent just before ActionInvoked, so the window can take focus on Wayland
+        "ActivationToken" => {
+            if let Ok((id, token)) = signal.body().deserialize::<(u32, String)>()
+                && ours.contains(&id)
+            {
+                let _ = tx.send(WindowMessage::ActivationToken(token));
+            }
+        }
+        "ActionInvoked" => {
+            if let Ok((id, _action)) = signal.body().deserialize::<(u32, String)>()
+                && ours.contains(&id)
+            {
+                debug!("Notification {id} clicked");
+                let _ = tx.send(WindowMessage::Trigger);
+            }
+        }
+        "NotificationClosed" => {
+            if let Ok((id, _reason)) = signal.body().deserialize::<(u32, u32)>() {
+                ours.remove(&id);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon_state::{Channel, ChannelKind};
+
+    const ALL: NotificationConfig = NotificationConfig {
+        connection: true,
+        devices: true,
+        profile: true,
+    };
+
+    fn device(id: &str) -> Device {
+        Device {
+            id: id.into(),
+            name: format!("{id} name"),
+            kind: ChannelKind::Source,
+        }
+    }
+
+    fn channel(id: &str) -> Channel {
+        Channel {
+            id: id.into(),
+            name: id.into(),
+            kind: ChannelKind::Source,
+            physical: true,
+            volume: 100,
+            muted: false,
+        }
+    }
+
+    fn state(profile: &str, channels: &[&str], devices: &[&str]) -> DaemonState {
+        DaemonState {
+            profile: Some(profile.into()),
+            channels: channels.iter().map(|id| channel(id)).collect(),
+            devices: devices.iter().map(|id| device(id)).collect(),
+        }
+    }
+
+    fn summaries(notifications: &[Notification]) -> Vec<(&str, &str)> {
+        notifications
+            .iter()
+            .map(|n| (n.summary.as_str(), n.body.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn device_presence() {
+        let before = state("Default", &["mic"], &["a", "b"]);
+        let after = state("Default", &["mic"], &["b", "c"]);
+        assert_eq!(
+            summaries(&state_changes(&ALL, &before, &after)),
+            vec![
+                ("Device connected", "c name"),
+                ("Device disconnected", "a name"),
+            ]
+        );
+    }
+
+    #[test]
+    fn editing_the_profile_is_not_a_device_change() {
+        let before = state("Default", &["mic"], &["a"]);
+        let after = state("Default", &["mic", "line"], &["a"]);
+        assert!(state_changes(&ALL, &before, &after).is_empty());
+    }
+
+    #[test]
+    fn switching_profiles_only_reports_the_profile() {
+        let before = state("Default", &["mic"], &["a"]);
+        let after = state("Streaming", &["line"], &["b"]);
+        assert_eq!(
+            summaries(&state_changes(&ALL, &before, &after)),
+            vec![("Profile changed", "Streaming")]
+        );
+    }
+
+    #[test]
+    fn follows_the_config() {
+        let before = state("Default", &[], &["a"]);
+        let after = state("Default", &[], &[]);
+        let none = NotificationConfig::default();
+        assert!(state_changes(&none, &before, &after).is_empty());
+
+        let after = state("Streaming", &[], &["a"]);
+        assert!(state_changes(&none, &before, &after).is_empty());
+    }
+}
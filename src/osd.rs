@@ -0,0 +1,43 @@
+use crate::config::OsdConfig;
+use qmetaobject::prelude::*;
+
+/// Settings for the on-screen display window
+#[derive(Default, QObject)]
+pub struct OsdProperties {
+    base: qt_base_class!(trait QObject),
+
+    // One of top-left, top, top-right, center, bottom-left, bottom or bottom-right
+    position: qt_property!(QString; NOTIFY position_changed),
+    position_changed: qt_signal!(),
+
+    // How long (in milliseconds) the OSD stays visible after the last change
+    timeout: qt_property!(i32; NOTIFY timeout_changed),
+    timeout_changed: qt_signal!(),
+
+    // Whether a change to the given channel (by ID and name) should show the OSD
+    shows_channel: qt_method!(fn(&self, id: QString, name: QString) -> bool),
+
+    channels: Vec<String>,
+}
+
+impl OsdProperties {
+    pub fn new(config: &OsdConfig) -> Self {
+        OsdProperties {
+            position: config.position.as_str().into(),
+            timeout: i32::try_from(config.timeout_ms).unwrap_or(i32::MAX),
+            channels: config.channels.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn shows_channel(&self, id: QString, name: QString) -> bool {
+        if self.channels.is_empty() {
+            return true;
+        }
+
+        let (id, name) = (id.to_string(), name.to_string());
+        self.channels
+            .iter()
+            .any(|channel| *channel == id || channel.eq_ignore_ascii_case(&name))
+    }
+}
@@ -0,0 +1,512 @@
+use crate::settings_file::{Placement, PlatformGeometry, WindowSettings, WindowState};
+use cpp::cpp;
+use log::debug;
+use qmetaobject::QString;
+use qttypes::QRectF;
+use serde::{Deserialize, Serialize};
+
+cpp! {{
+    #include <QGuiApplication>
+    #include <QRectF>
+    #include <QScreen>
+}}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    fn centre(&self) -> (i32, i32) {
+        (self.x + self.width / 2, self.y + self.height / 2)
+    }
+
+    fn contains(&self, (x, y): (i32, i32)) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+impl From<QRectF> for Rect {
+    fn from(rect: QRectF) -> Self {
+        Rect {
+            x: rect.x.round() as i32,
+            y: rect.y.round() as i32,
+            width: rect.width.round() as i32,
+            height: rect.height.round() as i32,
+        }
+    }
+}
+
+/// How a screen is remembered between runs, the name alone isn't enough as the same connector
+/// can have a different monitor plugged into it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ScreenId {
+    pub name: String,
+    pub width: i32,
+    pub height: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Screen {
+    pub name: String,
+    pub geometry: Rect,
+
+    // The geometry minus any panels or docks
+    pub available: Rect,
+    pub primary: bool,
+}
+
+impl Screen {
+    pub fn id(&self) -> ScreenId {
+        ScreenId {
+            name: self.name.clone(),
+            width: self.geometry.width,
+            height: self.geometry.height,
+        }
+    }
+}
+
+/// The Qt platform plugin in use, such as "xcb" or "wayland"
+pub fn platform_name() -> String {
+    let name = unsafe {
+        cpp!([] -> QString as "QString" {
+            return QGuiApplication::platformName();
+        })
+    };
+    name.to_string()
+}
+
+/// Wayland doesn't let clients position their own windows, or tell them where they are
+pub fn can_position_windows(platform: &str) -> bool {
+    !platform.starts_with("wayland")
+}
+
+/// Returns the currently connected screens, this must be called on the Qt thread
+pub fn screens() -> Vec<Screen> {
+    let count = unsafe {
+        cpp!([] -> i32 as "int" {
+            return QGuiApplication::screens().size();
+        })
+    };
+
+    (0..count).map(screen).collect()
+}
+
+fn screen(index: i32) -> Screen {
+    let name = unsafe {
+        cpp!([index as "int"] -> QString as "QString" {
+            return QGuiApplication::screens().at(index)->name();
+        })
+    };
+    let geometry = unsafe {
+        cpp!([index as "int"] -> QRectF as "QRectF" {
+            return QRectF(QGuiApplication::screens().at(index)->geometry());
+        })
+    };
+    let available = unsafe {
+        cpp!([index as "int"] -> QRectF as "QRectF" {
+            return QRectF(QGuiApplication::screens().at(index)->availableGeometry());
+        })
+    };
+    let primary = unsafe {
+        cpp!([index as "int"] -> bool as "bool" {
+            return QGuiApplication::screens().at(index) == QGuiApplication::primaryScreen();
+        })
+    };
+
+    Screen {
+        name: name.to_string(),
+        geometry: geometry.into(),
+        available: available.into(),
+        primary,
+    }
+}
+
+/// Identifies the current set of monitors, so a window can be put back where it was the last time
+/// this set was connected
+pub fn layout_key(screens: &[Screen]) -> String {
+    let mut ids: Vec<_> = screens
+        .iter()
+        .map(|screen| {
+            let Rect {
+                x,
+                y,
+                width,
+                height,
+            } = screen.geometry;
+            format!("{}:{width}x{height}+{x}+{y}", screen.name)
+        })
+        .collect();
+    ids.sort();
+    ids.join(",")
+}
+
+/// The screen a window is mostly on, going by where its centre is
+pub fn screen_at<'a>(window: &Rect, screens: &'a [Screen]) -> Option<&'a Screen> {
+    let centre = window.centre();
+    screens
+        .iter()
+        .find(|screen| screen.geometry.contains(centre))
+}
+
+/// The geometry saved for this platform. Until it has its own, it starts from whatever was saved
+/// before we split them by platform, as the size will still be good.
+pub fn saved_geometry(window: &WindowSettings, platform: &str) -> PlatformGeometry {
+    match window.platforms.get(platform) {
+        Some(saved) => saved.clone(),
+        None => window.fallback.clone().unwrap_or_default(),
+    }
+}
+
+/// Prefers wherever the window was the last time we had this set of monitors
+pub fn layout_placement<'a>(saved: &'a PlatformGeometry, screens: &[Screen]) -> &'a Placement {
+    let layout = layout_key(screens);
+    match saved.layouts.get(&layout) {
+        Some(placement) => {
+            debug!("Restoring the placement for monitor layout {layout}");
+            placement
+        }
+        None => &saved.placement,
+    }
+}
+
+/// Records a placement as the latest, and as the one for the current set of monitors
+pub fn remember(saved: &mut PlatformGeometry, placement: Placement, screens: &[Screen]) {
+    saved.placement = placement.clone();
+
+    // With no screens there's no layout to remember it against
+    if !screens.is_empty() {
+        saved.layouts.insert(layout_key(screens), placement);
+    }
+}
+
+/// Makes sure a restored window ends up somewhere visible. If the screen it was saved on is still
+/// connected (or it's otherwise still on a screen) it's kept where it was, as far as possible,
+/// otherwise it's centred on the primary screen.
+pub fn place(window: Rect, saved: Option<&ScreenId>, screens: &[Screen]) -> Rect {
+    // If Qt can't tell us anything, leave it to the window manager
+    if screens.is_empty() {
+        return window;
+    }
+
+    let on_saved = saved.and_then(|saved| screens.iter().find(|screen| screen.id() == *saved));
+    if let Some(screen) = on_saved.or_else(|| screen_at(&window, screens)) {
+        return clamp(window, &screen.available);
+    }
+
+    centre_on_primary(window, screens)
+}
+
+/// Works out where to open a window with the saved placement. Where we can position windows it's
+/// kept on a connected screen, otherwise the compositor decides and we only make sure it fits.
+pub fn restore(placement: &Placement, can_position: bool, screens: &[Screen]) -> Rect {
+    let size = Rect {
+        x: 0,
+        y: 0,
+        width: placement.width,
+        height: placement.height,
+    };
+
+    match (placement.x, placement.y) {
+        // Monitors may have been unplugged or rearranged since we last ran
+        (Some(x), Some(y)) if can_position => {
+            let saved = Rect { x, y, ..size };
+            let placed = place(saved, placement.screen.as_ref(), screens);
+            if placed != saved {
+                debug!(
+                    "Moved window onto a connected screen: {}x{} at ({}, {})",
+                    placed.width, placed.height, placed.x, placed.y
+                );
+            }
+            placed
+        }
+        _ => centre_on_primary(size, screens),
+    }
+}
+
+/// The placement to save for a window. On Wayland we're never told where it really is, so only
+/// the size is kept.
+pub fn placement(
+    window: Rect,
+    state: WindowState,
+    can_position: bool,
+    screens: &[Screen],
+) -> Placement {
+    let (x, y, screen) = if can_position {
+        let screen = screen_at(&window, screens).map(Screen::id);
+        (Some(window.x), Some(window.y), screen)
+    } else {
+        (None, None, None)
+    };
+
+    Placement {
+        width: window.width,
+        height: window.height,
+        x,
+        y,
+        screen,
+        state,
+    }
+}
+
+/// Centres the window on the primary screen, shrinking it if it doesn't fit
+pub fn centre_on_primary(window: Rect, screens: &[Screen]) -> Rect {
+    let primary = screens.iter().find(|screen| screen.primary);
+    match primary.or(screens.first()) {
+        Some(screen) => centre(window, &screen.available),
+        None => window,
+    }
+}
+
+fn clamp(window: Rect, area: &Rect) -> Rect {
+    let width = window.width.min(area.width);
+    let height = window.height.min(area.height);
+
+    Rect {
+        x: window.x.clamp(area.x, area.x + area.width - width),
+        y: window.y.clamp(area.y, area.y + area.height - height),
+        width,
+        height,
+    }
+}
+
+fn centre(window: Rect, area: &Rect) -> Rect {
+    let width = window.width.min(area.width);
+    let height = window.height.min(area.height);
+
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, width: i32, height: i32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    // A screen with a 40px panel along the top
+    fn screen(name: &str, x: i32, width: i32, primary: bool) -> Screen {
+        Screen {
+            name: name.into(),
+            geometry: rect(x, 0, width, 1080),
+            available: rect(x, 40, width, 1040),
+            primary,
+        }
+    }
+
+    // A 1920 wide primary on the left, and a 2560 wide screen to its right
+    fn dual() -> Vec<Screen> {
+        vec![
+            screen("DP-1", 0, 1920, true),
+            screen("HDMI-1", 1920, 2560, false),
+        ]
+    }
+
+    fn placement(width: i32, height: i32, x: Option<i32>, y: Option<i32>) -> Placement {
+        Placement {
+            width,
+            height,
+            x,
+            y,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn keeps_a_window_on_its_saved_screen() {
+        let screens = dual();
+        let window = rect(2200, 100, 1000, 600);
+        assert_eq!(place(window, Some(&screens[1].id()), &screens), window);
+
+        // Mostly off the side of it, but the saved screen still wins over the one it overlaps
+        let window = rect(1500, 100, 1000, 600);
+        assert_eq!(
+            place(window, Some(&screens[1].id()), &screens),
+            rect(1920, 100, 1000, 600)
+        );
+    }
+
+    #[test]
+    fn uses_whichever_screen_the_window_is_on() {
+        let screens = dual();
+        let window = rect(300, 10, 1000, 600);
+
+        // The saved screen has gone, but the window is still on another, clear of its panel
+        let gone = ScreenId {
+            name: "DP-2".into(),
+            width: 1920,
+            height: 1080,
+        };
+        assert_eq!(
+            place(window, Some(&gone), &screens),
+            rect(300, 40, 1000, 600)
+        );
+        assert_eq!(place(window, None, &screens), rect(300, 40, 1000, 600));
+    }
+
+    #[test]
+    fn a_replaced_monitor_is_not_the_saved_screen() {
+        let screens = dual();
+        let resized = ScreenId {
+            name: "HDMI-1".into(),
+            width: 1920,
+            height: 1080,
+        };
+        let window = rect(100, 100, 1000, 600);
+        assert_eq!(place(window, Some(&resized), &screens), window);
+    }
+
+    #[test]
+    fn centres_a_window_that_fell_off_every_screen() {
+        let screens = dual();
+        let window = rect(-3000, 5000, 1000, 600);
+        assert_eq!(place(window, None, &screens), rect(460, 260, 1000, 600));
+    }
+
+    #[test]
+    fn shrinks_a_window_larger_than_the_screen() {
+        let screens = dual();
+        let window = rect(0, 0, 4000, 3000);
+        assert_eq!(
+            place(window, Some(&screens[0].id()), &screens),
+            rect(0, 40, 1920, 1040)
+        );
+        assert_eq!(centre_on_primary(window, &screens), rect(0, 40, 1920, 1040));
+    }
+
+    #[test]
+    fn falls_back_to_the_first_screen_without_a_primary() {
+        let screens = vec![
+            screen("DP-1", 0, 1920, false),
+            screen("HDMI-1", 1920, 2560, false),
+        ];
+        let window = rect(-3000, 0, 1000, 600);
+        assert_eq!(place(window, None, &screens), rect(460, 260, 1000, 600));
+
+        // The primary isn't necessarily the first one Qt lists
+        let screens = vec![
+            screen("DP-1", 0, 1920, false),
+            screen("HDMI-1", 1920, 2560, true),
+        ];
+        assert_eq!(place(window, None, &screens), rect(2700, 260, 1000, 600));
+    }
+
+    #[test]
+    fn leaves_the_window_alone_without_screens() {
+        let window = rect(-3000, 0, 1000, 600);
+        assert_eq!(place(window, None, &[]), window);
+        assert_eq!(centre_on_primary(window, &[]), window);
+    }
+
+    #[test]
+    fn layout_key_ignores_order() {
+        let mut screens = dual();
+        let key = layout_key(&screens);
+        screens.reverse();
+        assert_eq!(layout_key(&screens), key);
+        assert_eq!(key, "DP-1:1920x1080+0+0,HDMI-1:2560x1080+1920+0");
+    }
+
+    #[test]
+    fn restores_the_saved_position() {
+        let screens = dual();
+        let saved = placement(1000, 600, Some(2200), Some(100));
+        assert_eq!(restore(&saved, true, &screens), rect(2200, 100, 1000, 600));
+    }
+
+    #[test]
+    fn restores_only_the_size_without_positioning() {
+        let screens = dual();
+        let saved = placement(1000, 600, Some(2200), Some(100));
+        assert_eq!(restore(&saved, false, &screens), rect(460, 260, 1000, 600));
+
+        // Saved on Wayland, then restored somewhere we can position windows
+        let saved = placement(1000, 600, None, None);
+        assert_eq!(restore(&saved, true, &screens), rect(460, 260, 1000, 600));
+    }
+
+    #[test]
+    fn saves_the_position_and_screen() {
+        let screens = dual();
+        let saved = placement_of(true, &screens);
+        assert_eq!((saved.x, saved.y), (Some(2200), Some(100)));
+        assert_eq!(saved.screen, Some(screens[1].id()));
+        assert_eq!((saved.width, saved.height), (1000, 600));
+        assert_eq!(saved.state, WindowState::Maximized);
+    }
+
+    #[test]
+    fn saves_only_the_size_without_positioning() {
+        let screens = dual();
+        let saved = placement_of(false, &screens);
+        assert_eq!((saved.x, saved.y, saved.screen), (None, None, None));
+        assert_eq!((saved.width, saved.height), (1000, 600));
+        assert_eq!(saved.state, WindowState::Maximized);
+    }
+
+    fn placement_of(can_position: bool, screens: &[Screen]) -> Placement {
+        let window = rect(2200, 100, 1000, 600);
+        super::placement(window, WindowState::Maximized, can_position, screens)
+    }
+
+    #[test]
+    fn platforms_start_from_the_fallback() {
+        let mut window = WindowSettings::default();
+        assert_eq!(saved_geometry(&window, "xcb").placement.width, 1000);
+
+        let mut fallback = PlatformGeometry::default();
+        fallback.placement.width = 1200;
+        window.fallback = Some(fallback);
+        assert_eq!(saved_geometry(&window, "xcb").placement.width, 1200);
+
+        let mut wayland = PlatformGeometry::default();
+        wayland.placement.width = 1400;
+        window.platforms.insert("wayland".into(), wayland);
+        assert_eq!(saved_geometry(&window, "wayland").placement.width, 1400);
+        assert_eq!(saved_geometry(&window, "xcb").placement.width, 1200);
+    }
+
+    #[test]
+    fn remembers_a_placement_for_each_layout() {
+        let laptop = vec![screen("eDP-1", 0, 1920, true)];
+        let docked = dual();
+
+        let mut saved = PlatformGeometry::default();
+        remember(&mut saved, placement(800, 500, Some(10), Some(50)), &laptop);
+        remember(
+            &mut saved,
+            placement(1600, 900, Some(2000), Some(50)),
+            &docked,
+        );
+        assert_eq!(saved.layouts.len(), 2);
+
+        assert_eq!(layout_placement(&saved, &laptop).width, 800);
+        assert_eq!(layout_placement(&saved, &docked).width, 1600);
+
+        // A layout we've not seen uses whichever placement was saved last
+        let other = vec![screen("DP-3", 0, 2560, true)];
+        assert_eq!(layout_placement(&saved, &other).width, 1600);
+    }
+
+    #[test]
+    fn remembers_no_layout_without_screens() {
+        let mut saved = PlatformGeometry::default();
+        remember(&mut saved, placement(800, 500, None, None), &[]);
+        assert_eq!(saved.placement.width, 800);
+        assert!(saved.layouts.is_empty());
+    }
+}
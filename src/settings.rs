@@ -0,0 +1,36 @@
+use crate::autostart;
+use log::warn;
+use qmetaobject::prelude::*;
+
+/// Application settings which are managed from the UI side
+#[derive(Default, QObject)]
+pub struct AppSettings {
+    base: qt_base_class!(trait QObject),
+
+    // Whether the app is launched (in the background) when the user logs in
+    autostart: qt_property!(bool; WRITE set_autostart NOTIFY autostart_changed),
+    autostart_changed: qt_signal!(),
+}
+
+impl AppSettings {
+    pub fn new() -> Self {
+        AppSettings {
+            autostart: autostart::is_enabled(),
+            ..Default::default()
+        }
+    }
+
+    fn set_autostart(&mut self, enabled: bool) {
+        if enabled == self.autostart {
+            return;
+        }
+
+        match autostart::set_enabled(enabled) {
+            Ok(()) => self.autostart = enabled,
+            Err(e) => warn!("Unable to change autostart: {e:#}"),
+        }
+
+        // Emitted regardless, so anything bound to this reverts if the change failed
+        self.autostart_changed();
+    }
+}
@@ -0,0 +1,527 @@
+use crate::screens::ScreenId;
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Bump this, and handle the older version in `parse`, when the format changes in a way that old
+// files can't simply be read as
+const VERSION: u32 = 1;
+
+/// Everything the app remembers between runs, stored in ~/.config/pipeweaver/settings.json
+#[derive(Serialize, Deserialize)]
+pub struct Settings {
+    pub version: u32,
+
+    #[serde(default)]
+    pub window: WindowSettings,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mixer: Option<MixerGeometry>,
+    #[serde(default)]
+    pub preferences: Preferences,
+
+    // Set when the file exists but couldn't be read, or was written by a newer version, so we
+    // don't replace it with the defaults or lose what we don't understand of it
+    #[serde(skip)]
+    read_only: bool,
+
+    // Where the settings live, normally ~/.config/pipeweaver
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            version: VERSION,
+            window: WindowSettings::default(),
+            mixer: None,
+            preferences: Preferences::default(),
+            read_only: false,
+            dir: Self::get_config_dir(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WindowSettings {
+    // Kept separately for each Qt platform (such as xcb or wayland), so switching between X11 and
+    // Wayland sessions doesn't mix them up
+    #[serde(default)]
+    pub platforms: BTreeMap<String, PlatformGeometry>,
+
+    // Carried over from geometry saved without a platform, each platform starts from it until it
+    // has its own entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<PlatformGeometry>,
+
+    // Whether the window was visible when the app last exited. Only --hidden starts us hidden,
+    // this is kept for reference rather than acted on.
+    #[serde(default = "default_visible")]
+    pub visible: bool,
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        WindowSettings {
+            platforms: BTreeMap::new(),
+            fallback: None,
+            visible: true,
+        }
+    }
+}
+
+// Everything we remember about the window's geometry on one platform
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct PlatformGeometry {
+    // The most recent placement, used for monitor layouts we've not seen before
+    #[serde(flatten)]
+    pub placement: Placement,
+
+    // The placement last used with each monitor layout
+    #[serde(default)]
+    pub layouts: BTreeMap<String, Placement>,
+}
+
+// Where the window was, and how. The size and position are always the un-maximized geometry.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Placement {
+    pub width: i32,
+    pub height: i32,
+
+    // Only saved where we're able to position windows ourselves, so not on Wayland
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<i32>,
+
+    // The screen the window was on, so we can tell if it's gone away
+    #[serde(default)]
+    pub screen: Option<ScreenId>,
+    #[serde(default)]
+    pub state: WindowState,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Placement {
+            width: 1000, // Match minimumWidth from QML
+            height: 600, // Match minimumHeight from QML
+            x: Some(100),
+            y: Some(100),
+            screen: None,
+            state: WindowState::Normal,
+        }
+    }
+}
+
+// The state of the window when it was last shown
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowState {
+    #[default]
+    Normal,
+    Maximized,
+    FullScreen,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MixerGeometry {
+    // Kept for each Qt platform, the same as the main window
+    #[serde(default)]
+    pub platforms: BTreeMap<String, Placement>,
+
+    // Geometry saved before it was kept by platform, which each starts from until it has its own
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<Placement>,
+
+    // Whether the mixer was open when the app last exited
+    #[serde(default)]
+    pub visible: bool,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Preferences {
+    // Whether closing the window hides it rather than exiting the app
+    #[serde(default)]
+    pub close_to_tray: bool,
+}
+
+// window.json, from before everything was kept in the one file
+#[derive(Deserialize)]
+struct LegacyWindow {
+    #[serde(default)]
+    platforms: BTreeMap<String, PlatformGeometry>,
+    #[serde(flatten)]
+    geometry: Option<PlatformGeometry>,
+
+    #[serde(default)]
+    close_to_tray: bool,
+    #[serde(default = "default_visible")]
+    visible: bool,
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+impl Settings {
+    fn get_config_dir() -> PathBuf {
+        let mut path = dirs::config_dir().unwrap_or_else(|| PathBuf::from("."));
+        path.push("pipeweaver");
+        path
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join("settings.json")
+    }
+
+    /// Loads the settings, carrying over anything from older versions of the app. If the file
+    /// can't be read it's moved out of the way, and we start again with the defaults.
+    pub fn load() -> Self {
+        Self::load_from(Self::get_config_dir())
+    }
+
+    fn load_from(dir: PathBuf) -> Self {
+        let defaults = Settings {
+            dir,
+            ..Self::default()
+        };
+        let path = defaults.path();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return defaults.migrate(),
+            Err(e) => {
+                warn!("Unable to read {path:?}, using the default settings: {e}");
+                warn!("Changes to the settings won't be saved until it can be read");
+                return Settings {
+                    read_only: true,
+                    ..defaults
+                };
+            }
+        };
+
+        match Self::parse(&content) {
+            Ok(settings) => {
+                debug!("Loaded settings from {path:?}");
+                Settings {
+                    dir: defaults.dir,
+                    ..settings
+                }
+            }
+            Err(e) => {
+                backup(&path, &e, "Every window position and app preference");
+                defaults
+            }
+        }
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let mut settings: Settings = serde_json::from_str(content)?;
+        if settings.version > VERSION {
+            warn!(
+                "Settings were saved by a newer version of the app (v{}), changes won't be saved",
+                settings.version
+            );
+            settings.read_only = true;
+        }
+        Ok(settings)
+    }
+
+    // Fills in the defaults from the separate files older versions wrote
+    fn migrate(mut self) -> Self {
+        let window_path = self.dir.join("window.json");
+        let mixer_path = self.dir.join("mixer.json");
+        let mut migrated = vec![];
+
+        if let Some(window) = read_legacy::<LegacyWindow>(&window_path, "The window position") {
+            self.window = WindowSettings {
+                platforms: window.platforms,
+                fallback: window.geometry,
+                visible: window.visible,
+            };
+            self.preferences.close_to_tray = window.close_to_tray;
+            migrated.push(window_path);
+        }
+        if let Some(mixer) = read_legacy::<MixerGeometry>(&mixer_path, "The mixer position") {
+            self.mixer = Some(mixer);
+            migrated.push(mixer_path);
+        }
+
+        if migrated.is_empty() {
+            return self;
+        }
+
+        // Only remove the old files once their contents are safely in the new one
+        info!("Moving settings from {migrated:?} into {:?}", self.path());
+        if self.try_save().inspect_err(|e| warn!("{e:#}")).is_ok() {
+            for path in migrated {
+                fs::remove_file(&path).ok();
+            }
+        }
+        self
+    }
+
+    /// Forgets where the windows were, so they open at their default size and position
+    pub fn reset_geometry(&mut self) {
+        self.window.platforms.clear();
+        self.window.fallback = None;
+        self.mixer = None;
+    }
+
+    pub fn save(&self) {
+        if let Err(e) = self.try_save() {
+            warn!("{e:#}");
+        }
+    }
+
+    // Written to a temporary file which replaces the real one, so a crash part way through
+    // can't leave it half written
+    fn try_save(&self) -> Result<()> {
+        let path = self.path();
+        if self.read_only {
+            debug!("Not saving settings over {path:?}, as it couldn't be read or is newer");
+            return Ok(());
+        }
+
+        let dir = &self.dir;
+        fs::create_dir_all(dir).with_context(|| format!("Unable to create {dir:?}"))?;
+
+        let json = serde_json::to_string_pretty(self)?;
+        let temp = path.with_extension("json.tmp");
+        let write = || -> Result<()> {
+            let mut file = File::create(&temp)?;
+            file.write_all(json.as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&temp, &path)?;
+            Ok(())
+        };
+
+        write().with_context(|| format!("Unable to save settings to {path:?}"))
+    }
+}
+
+fn read_legacy<T: for<'de> Deserialize<'de>>(path: &Path, contents: &str) -> Option<T> {
+    let content = fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&content) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            backup(path, &e.into(), contents);
+            None
+        }
+    }
+}
+
+// Keeps a copy of a file we couldn't understand, so the user can recover anything from it
+fn backup(path: &Path, error: &anyhow::Error, contents: &str) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default();
+
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".{timestamp}.bak"));
+    let backup = PathBuf::from(backup);
+
+    match fs::rename(path, &backup) {
+        Ok(()) => warn!("Unable to parse {path:?} ({error}), it has been moved to {backup:?}"),
+        Err(e) => warn!("Unable to parse {path:?} ({error}), and couldn't back it up: {e}"),
+    }
+    warn!("{contents} has been reset to the default");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // A fresh directory for each test, removed again afterwards
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let name = format!(
+                "pipeweaver-settings-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            );
+            let dir = std::env::temp_dir().join(name);
+            fs::create_dir_all(&dir).unwrap();
+            TestDir(dir)
+        }
+
+        fn write(&self, name: &str, content: &str) {
+            fs::write(self.0.join(name), content).unwrap();
+        }
+
+        fn read(&self, name: &str) -> Option<String> {
+            fs::read_to_string(self.0.join(name)).ok()
+        }
+
+        fn files(&self) -> Vec<String> {
+            let mut files: Vec<_> = fs::read_dir(&self.0)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            files.sort();
+            files
+        }
+
+        fn load(&self) -> Settings {
+            Settings::load_from(self.0.clone())
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn defaults_without_any_files() {
+        let dir = TestDir::new();
+        let settings = dir.load();
+        assert!(settings.window.platforms.is_empty());
+        assert!(settings.mixer.is_none());
+        assert!(!settings.preferences.close_to_tray);
+
+        // Nothing to migrate, so nothing is written until something changes
+        assert!(dir.files().is_empty());
+    }
+
+    #[test]
+    fn round_trips() {
+        let dir = TestDir::new();
+        let mut settings = dir.load();
+        settings.preferences.close_to_tray = true;
+        settings.window.visible = false;
+        settings.window.platforms.insert(
+            "xcb".into(),
+            PlatformGeometry {
+                placement: Placement {
+                    width: 1200,
+                    ..Default::default()
+                },
+                layouts: BTreeMap::new(),
+            },
+        );
+        settings.save();
+
+        let settings = dir.load();
+        assert!(settings.preferences.close_to_tray);
+        assert!(!settings.window.visible);
+        assert_eq!(settings.window.platforms["xcb"].placement.width, 1200);
+    }
+
+    #[test]
+    fn migrates_legacy_files() {
+        let dir = TestDir::new();
+        dir.write(
+            "window.json",
+            r#"{"width": 1280, "height": 720, "x": 10, "y": 20, "close_to_tray": true,
+                "visible": false, "platforms": {"wayland": {"width": 900, "height": 700}}}"#,
+        );
+        dir.write(
+            "mixer.json",
+            r#"{"width": 300, "height": 500, "x": 5, "y": 6, "visible": true}"#,
+        );
+
+        let settings = dir.load();
+        let fallback = settings.window.fallback.as_ref().unwrap();
+        assert_eq!(
+            (fallback.placement.width, fallback.placement.x),
+            (1280, Some(10))
+        );
+        assert_eq!(settings.window.platforms["wayland"].placement.width, 900);
+        assert!(settings.preferences.close_to_tray);
+        assert!(!settings.window.visible);
+
+        let mixer = settings.mixer.as_ref().unwrap();
+        assert_eq!(mixer.fallback.as_ref().unwrap().width, 300);
+        assert!(mixer.visible);
+
+        // The old files are only removed once the new one has been written
+        assert_eq!(dir.files(), vec!["settings.json"]);
+        assert_eq!(dir.load().window.fallback.unwrap().placement.height, 720);
+    }
+
+    #[test]
+    fn backs_up_unparseable_files() {
+        let dir = TestDir::new();
+        dir.write("settings.json", "{ not json");
+
+        let settings = dir.load();
+        assert!(settings.window.platforms.is_empty());
+
+        let files = dir.files();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].starts_with("settings.json.") && files[0].ends_with(".bak"));
+        assert_eq!(dir.read(&files[0]).as_deref(), Some("{ not json"));
+    }
+
+    #[test]
+    fn backs_up_unparseable_legacy_files() {
+        let dir = TestDir::new();
+        dir.write("window.json", "[]");
+        dir.write("mixer.json", r#"{"width": 300, "height": 500}"#);
+
+        let settings = dir.load();
+        assert!(settings.window.fallback.is_none());
+        assert!(settings.mixer.is_some());
+
+        let files = dir.files();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0], "settings.json");
+        assert!(files[1].starts_with("window.json.") && files[1].ends_with(".bak"));
+    }
+
+    #[test]
+    fn does_not_save_over_an_unreadable_file() {
+        let dir = TestDir::new();
+
+        // A directory can't be read as a file, but isn't missing either
+        fs::create_dir(dir.0.join("settings.json")).unwrap();
+        let mut settings = dir.load();
+        assert!(settings.read_only);
+
+        settings.preferences.close_to_tray = true;
+        assert!(settings.try_save().is_ok());
+        assert!(dir.0.join("settings.json").is_dir());
+    }
+
+    #[test]
+    fn does_not_save_over_a_newer_version() {
+        let dir = TestDir::new();
+        let newer = r#"{"version": 99, "preferences": {"close_to_tray": true}, "future": 1}"#;
+        dir.write("settings.json", newer);
+
+        let mut settings = dir.load();
+        assert!(settings.read_only);
+        assert!(settings.preferences.close_to_tray);
+
+        settings.preferences.close_to_tray = false;
+        settings.save();
+        assert_eq!(dir.read("settings.json").as_deref(), Some(newer));
+    }
+
+    #[test]
+    fn saves_through_a_temporary_file() {
+        let dir = TestDir::new();
+        let mut settings = dir.load();
+        settings.save();
+        let saved = dir.read("settings.json").unwrap();
+        assert_eq!(dir.files(), vec!["settings.json"]);
+
+        // If the temporary file can't be written, the real one is left alone
+        fs::create_dir(dir.0.join("settings.json.tmp")).unwrap();
+        settings.preferences.close_to_tray = true;
+        assert!(settings.try_save().is_err());
+        assert_eq!(dir.read("settings.json"), Some(saved));
+    }
+}
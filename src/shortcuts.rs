@@ -0,0 +1,194 @@
+use crate::config::{Shortcut, ShortcutAction};
+use crate::daemon_api::{DEFAULT_TIMEOUT, DaemonClient, DaemonRequest};
+use crate::window_handler::WindowMessage;
+use anyhow::{Context, Result, bail};
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::sync::mpsc;
+use zbus::blocking::Connection;
+use zbus::proxy;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+
+#[proxy(
+    interface = "org.freedesktop.portal.GlobalShortcuts",
+    default_service = "org.freedesktop.portal.Desktop",
+    default_path = "/org/freedesktop/portal/desktop"
+)]
+trait GlobalShortcuts {
+    fn create_session(&self, options: HashMap<&str, Value<'_>>) -> zbus::Result<OwnedObjectPath>;
+
+    fn bind_shortcuts(
+        &self,
+        session_handle: &ObjectPath<'_>,
+        shortcuts: &[(&str, HashMap<&str, Value<'_>>)],
+        parent_window: &str,
+        options: HashMap<&str, Value<'_>>,
+    ) -> zbus::Result<OwnedObjectPath>;
+
+    #[zbus(signal)]
+    fn activated(
+        &self,
+        session_handle: ObjectPath<'_>,
+        shortcut_id: &str,
+        timestamp: u64,
+        options: HashMap<String, OwnedValue>,
+    ) -> zbus::Result<()>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.portal.Request",
+    default_service = "org.freedesktop.portal.Desktop"
+)]
+trait Request {
+    #[zbus(signal)]
+    fn response(&self, response: u32, results: HashMap<String, OwnedValue>) -> zbus::Result<()>;
+}
+
+/// Registers the shortcuts with the portal, then dispatches them as they're activated. This
+/// runs for as long as the portal session is alive.
+pub fn shortcuts_thread_main(
+    shortcuts: Vec<Shortcut>,
+    tx: mpsc::Sender<WindowMessage>,
+    client: DaemonClient,
+) -> Result<()> {
+    let connection = Connection::session()?;
+    let portal = GlobalShortcutsProxyBlocking::new(&connection)?;
+
+    let results = portal_request(&connection, |token| {
+        portal.create_session(HashMap::from([
+            ("handle_token", Value::from(token)),
+            ("session_handle_token", Value::from(token)),
+        ]))
+    })?;
+    let session = match results.get("session_handle").map(|value| &**value) {
+        Some(Value::Str(handle)) => ObjectPath::try_from(handle.as_str())?.into_owned(),
+        Some(Value::ObjectPath(handle)) => handle.to_owned(),
+        _ => bail!("Portal didn't return a session handle"),
+    };
+    debug!("Created GlobalShortcuts session {}", session.as_str());
+
+    // Subscribe before binding, so we can't miss an activation
+    let activations = portal.receive_activated()?;
+
+    let bindings: Vec<_> = shortcuts
+        .iter()
+        .map(|shortcut| {
+            let description = shortcut.description.as_deref().unwrap_or(&shortcut.id);
+            let mut options = HashMap::from([("description", Value::from(description))]);
+            if let Some(trigger) = &shortcut.trigger {
+                options.insert("preferred_trigger", Value::from(trigger.as_str()));
+            }
+            (shortcut.id.as_str(), options)
+        })
+        .collect();
+
+    portal_request(&connection, |token| {
+        portal.bind_shortcuts(
+            &session,
+            &bindings,
+            "",
+            HashMap::from([("handle_token", Value::from(token))]),
+        )
+    })?;
+    info!("Registered {} global shortcut(s)", shortcuts.len());
+
+    for signal in activations {
+        let args = signal.args()?;
+        if args.session_handle != session {
+            continue;
+        }
+
+        let Some(shortcut) = shortcuts.iter().find(|s| s.id == args.shortcut_id) else {
+            warn!("Activated unknown shortcut {}", args.shortcut_id);
+            continue;
+        };
+        debug!("Shortcut {} activated", shortcut.id);
+
+        // Newer portals let us take focus when a shortcut is pressed
+        if let Some(Value::Str(token)) = args.options.get("activation_token").map(|v| &**v) {
+            let _ = tx.send(WindowMessage::ActivationToken(token.to_string()));
+        }
+
+        match &shortcut.action {
+            ShortcutAction::Show => {
+                let _ = tx.send(WindowMessage::Trigger);
+            }
+            ShortcutAction::Hide => {
+                let _ = tx.send(WindowMessage::Hide);
+            }
+            ShortcutAction::Toggle => {
+                let _ = tx.send(WindowMessage::Toggle);
+            }
+            ShortcutAction::Daemon(command) => {
+                let request = DaemonRequest::Raw(command.clone());
+                if let Err(e) = client.request(request, DEFAULT_TIMEOUT) {
+                    warn!("Shortcut {} failed: {e}", shortcut.id);
+                }
+            }
+        }
+    }
+
+    info!("GlobalShortcuts session closed");
+    Ok(())
+}
+
+/// Portal methods return a Request handle, with the actual result sent later as a signal on it.
+/// The handle's path is predictable, so we subscribe to it before making the call.
+fn portal_request<F>(connection: &Connection, call: F) -> Result<HashMap<String, OwnedValue>>
+where
+    F: FnOnce(&str) -> zbus::Result<OwnedObjectPath>,
+{
+    let token = format!("pipeweaver_{}", fastrand::u32(..));
+    let sender = connection
+        .unique_name()
+        .context("Not connected to the session bus")?;
+
+    let request = RequestProxyBlocking::builder(connection)
+        .path(request_path(sender, &token))?
+        .build()?;
+    let mut responses = request.receive_response()?;
+
+    call(&token)?;
+
+    let Some(response) = responses.next() else {
+        bail!("Portal request was dropped");
+    };
+    let args = response.args()?;
+    match args.response {
+        0 => Ok(args.results),
+        1 => bail!("Portal request was cancelled"),
+        _ => bail!("Portal request failed"),
+    }
+}
+
+// Where the portal puts the Request for a call, made up of our unique name (minus the ':', and
+// with '.' replaced by '_') and the handle_token we passed it
+fn request_path(sender: &str, token: &str) -> String {
+    let sender = sender.trim_start_matches(':').replace('.', "_");
+    format!("{PORTAL_PATH}/request/{sender}/{token}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_path_follows_the_portal_spec() {
+        assert_eq!(
+            request_path(":1.42", "pipeweaver_7"),
+            "/org/freedesktop/portal/desktop/request/1_42/pipeweaver_7"
+        );
+        assert_eq!(
+            request_path(":1.2.3", "token"),
+            "/org/freedesktop/portal/desktop/request/1_2_3/token"
+        );
+    }
+
+    #[test]
+    fn request_path_is_a_valid_object_path() {
+        let token = format!("pipeweaver_{}", u32::MAX);
+        assert!(ObjectPath::try_from(request_path(":1.42", &token)).is_ok());
+    }
+}
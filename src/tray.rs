@@ -0,0 +1,263 @@
+use crate::APP_NAME;
+use crate::window_handler::WindowMessage;
+use anyhow::Result;
+use ksni::blocking::{Handle, TrayMethods};
+use ksni::menu::{CheckmarkItem, StandardItem};
+use ksni::{MenuItem, ToolTip, Tray};
+use log::debug;
+use std::sync::mpsc;
+
+/// A StatusNotifierItem, served over D-Bus, which reflects the state of the app
+pub struct PipeweaverTray {
+    tx: mpsc::Sender<WindowMessage>,
+
+    connected: bool,
+    visible: bool,
+    close_to_tray: bool,
+}
+
+impl PipeweaverTray {
+    fn send(&self, message: WindowMessage) {
+        let _ = self.tx.send(message);
+    }
+}
+
+impl Tray for PipeweaverTray {
+    fn id(&self) -> String {
+        APP_NAME.into()
+    }
+
+    // A left click toggles the window
+    fn activate(&mut self, _x: i32, _y: i32) {
+        self.send(WindowMessage::Toggle);
+    }
+
+    fn title(&self) -> String {
+        "Pipeweaver".into()
+    }
+
+    fn icon_name(&self) -> String {
+        "pipeweaver".into()
+    }
+
+    fn overlay_icon_name(&self) -> String {
+        if self.connected {
+            String::new()
+        } else {
+            "network-offline".into()
+        }
+    }
+
+    fn tool_tip(&self) -> ToolTip {
+        let description = if self.connected {
+            "Connected to Pipeweaver"
+        } else {
+            "Not connected to Pipeweaver"
+        };
+
+        ToolTip {
+            icon_name: self.icon_name(),
+            title: self.title(),
+            description: description.into(),
+            ..Default::default()
+        }
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        let (label, message): (&str, fn() -> WindowMessage) = if self.visible {
+            ("Hide", || WindowMessage::Hide)
+        } else {
+            ("Show", || WindowMessage::Trigger)
+        };
+
+        vec![
+            StandardItem {
+                label: label.into(),
+                activate: Box::new(move |this: &mut Self| this.send(message())),
+                ..Default::default()
+            }
+            .into(),
+            StandardItem {
+                label: "Mini Mixer".into(),
+                icon_name: "audio-volume-high".into(),
+                activate: Box::new(|this: &mut Self| this.send(WindowMessage::ShowMixer)),
+                ..Default::default()
+            }
+            .into(),
+            StandardItem {
+                label: "Reload".into(),
+                icon_name: "view-refresh".into(),
+                activate: Box::new(|this: &mut Self| this.send(WindowMessage::Reload)),
+                ..Default::default()
+            }
+            .into(),
+            MenuItem::Separator,
+            CheckmarkItem {
+                label: "Close to Tray".into(),
+                checked: self.close_to_tray,
+                activate: Box::new(|this: &mut Self| {
+                    this.close_to_tray = !this.close_to_tray;
+                    this.send(WindowMessage::CloseToTray(this.close_to_tray));
+                }),
+                ..Default::default()
+            }
+            .into(),
+            MenuItem::Separator,
+            StandardItem {
+                label: "Quit".into(),
+                icon_name: "application-exit".into(),
+                activate: Box::new(|this: &mut Self| this.send(WindowMessage::Quit)),
+                ..Default::default()
+            }
+            .into(),
+        ]
+    }
+}
+
+/// Used by the Qt side to push state changes to the tray
+pub struct TrayHandle {
+    handle: Handle<PipeweaverTray>,
+}
+
+impl TrayHandle {
+    pub fn spawn(tx: mpsc::Sender<WindowMessage>, close_to_tray: bool) -> Result<Self> {
+        let tray = PipeweaverTray {
+            tx,
+            connected: false,
+            visible: false,
+            close_to_tray,
+        };
+
+        let handle = tray.spawn()?;
+        debug!("Tray icon registered");
+        Ok(Self { handle })
+    }
+
+    pub fn set_connected(&self, connected: bool) {
+        self.handle.update(|tray| tray.connected = connected);
+    }
+
+    pub fn set_visible(&self, visible: bool) {
+        self.handle.update(|tray| tray.visible = visible);
+    }
+}
+
+impl Drop for TrayHandle {
+    fn drop(&mut self) {
+        self.handle.shutdown().wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tray(visible: bool, close_to_tray: bool) -> (PipeweaverTray, mpsc::Receiver<WindowMessage>) {
+        let (tx, rx) = mpsc::channel();
+        let tray = PipeweaverTray {
+            tx,
+            connected: true,
+            visible,
+            close_to_tray,
+        };
+        (tray, rx)
+    }
+
+    fn labels(tray: &PipeweaverTray) -> Vec<String> {
+        tray.menu()
+            .iter()
+            .map(|item| match item {
+                MenuItem::Standard(item) => item.label.clone(),
+                MenuItem::Checkmark(item) => format!("{} ({})", item.label, item.checked),
+                MenuItem::Separator => "-".into(),
+                _ => "?".into(),
+            })
+            .collect()
+    }
+
+    // Clicks the menu item with the label, as the tray host would
+    fn click(tray: &mut PipeweaverTray, label: &str) {
+        let menu = tray.menu();
+        let activate = menu
+            .iter()
+            .find_map(|item| match item {
+                MenuItem::Standard(item) if item.label == label => Some(&item.activate),
+                MenuItem::Checkmark(item) if item.label == label => Some(&item.activate),
+                _ => None,
+            })
+            .unwrap();
+        activate(tray);
+    }
+
+    #[test]
+    fn menu_follows_the_window() {
+        let (mut shown, rx) = tray(true, false);
+        assert_eq!(
+            labels(&shown),
+            vec![
+                "Hide",
+                "Mini Mixer",
+                "Reload",
+                "-",
+                "Close to Tray (false)",
+                "-",
+                "Quit"
+            ]
+        );
+        click(&mut shown, "Hide");
+        assert!(matches!(rx.try_recv(), Ok(WindowMessage::Hide)));
+
+        let (mut hidden, rx) = tray(false, false);
+        assert_eq!(labels(&hidden)[0], "Show");
+        click(&mut hidden, "Show");
+        assert!(matches!(rx.try_recv(), Ok(WindowMessage::Trigger)));
+    }
+
+    #[test]
+    fn close_to_tray_toggles() {
+        let (mut tray, rx) = tray(true, true);
+        assert!(labels(&tray).contains(&"Close to Tray (true)".to_string()));
+
+        click(&mut tray, "Close to Tray");
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(WindowMessage::CloseToTray(false))
+        ));
+        assert!(labels(&tray).contains(&"Close to Tray (false)".to_string()));
+
+        click(&mut tray, "Close to Tray");
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(WindowMessage::CloseToTray(true))
+        ));
+    }
+
+    #[test]
+    fn other_items_send_their_messages() {
+        let (mut tray, rx) = tray(true, false);
+        click(&mut tray, "Mini Mixer");
+        click(&mut tray, "Reload");
+        click(&mut tray, "Quit");
+        tray.activate(0, 0);
+
+        let messages: Vec<_> = rx.try_iter().collect();
+        assert!(matches!(
+            messages[..],
+            [
+                WindowMessage::ShowMixer,
+                WindowMessage::Reload,
+                WindowMessage::Quit,
+                WindowMessage::Toggle
+            ]
+        ));
+    }
+
+    #[test]
+    fn shows_when_disconnected() {
+        let (mut tray, _rx) = tray(true, false);
+        assert_eq!(tray.overlay_icon_name(), "");
+        tray.connected = false;
+        assert_eq!(tray.overlay_icon_name(), "network-offline");
+        assert_eq!(tray.tool_tip().description, "Not connected to Pipeweaver");
+    }
+}
@@ -0,0 +1,346 @@
+use crate::daemon_api::{
+    ClientRequest, DaemonRequest, DaemonResponse, PendingRequests, RequestQueue, WebsocketResponse,
+};
+use crate::daemon_state::{DaemonMirror, Update};
+use crate::notifications::DaemonEvent;
+use crate::window_handler::WindowMessage;
+use anyhow::Result;
+use log::{debug, error, info, warn};
+use std::io::{self, ErrorKind};
+use std::net::TcpStream;
+use std::os::fd::AsRawFd;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tungstenite::http::Uri;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket, connect};
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// Controls how we behave when the connection to the daemon is lost
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+
+    // If set, how long we keep trying before giving up and closing the window
+    pub give_up_after: Option<Duration>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(10),
+            give_up_after: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Returns the delay before the given (zero based) reconnect attempt. This doubles on each
+    /// attempt up to max_delay, then has 'equal jitter' applied so that if multiple clients are
+    /// waiting on a daemon restart, they don't all hammer it at the same time.
+    fn delay_for(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+
+        let half = backoff / 2;
+        half + half.mul_f64(fastrand::f64())
+    }
+}
+
+/// Controls how long we'll wait for the daemon to appear when starting up
+#[derive(Debug, Clone, Copy)]
+pub struct WaitPolicy {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+pub fn websocket_main_thread(
+    uri: Uri,
+    res: mpsc::Sender<Result<()>>,
+    tx: mpsc::Sender<WindowMessage>,
+    requests: RequestQueue,
+    events: mpsc::Sender<DaemonEvent>,
+    policy: ReconnectPolicy,
+    wait: Option<WaitPolicy>,
+) {
+    // We need to spawn up a Websocket connection, then simply read from it until closed
+    info!("Attempting to connect to Pipeweaver at {uri}");
+    let mut socket = match initial_connect(&uri, wait) {
+        Ok(socket) => socket,
+        Err(e) => {
+            let _ = res.send(Err(e));
+
+            // If we were waiting, the window will already be open, so exit the app.
+            if wait.is_some() {
+                let _ = tx.send(WindowMessage::Quit);
+            }
+            return;
+        }
+    };
+    requests.set_connected(true);
+    let _ = res.send(Ok(()));
+    let _ = tx.send(WindowMessage::Connected);
+
+    loop {
+        run_connection(&mut socket, &requests, &tx, &events);
+
+        // Requests made from here on fail straight away, rather than waiting on a reconnect
+        requests.set_connected(false);
+        info!("Connection to Pipeweaver Lost, attempting to reconnect");
+        let _ = tx.send(WindowMessage::Disconnected);
+        let _ = events.send(DaemonEvent::ConnectionLost);
+
+        match reconnect(&uri, &policy) {
+            Some(new_socket) => {
+                info!("Reconnected to Pipeweaver");
+                socket = new_socket;
+                requests.set_connected(true);
+                let _ = tx.send(WindowMessage::Reconnected);
+                let _ = events.send(DaemonEvent::ConnectionRestored);
+            }
+            None => {
+                // We've run out of time, exit the app (closing the window may only hide it)
+                info!("Unable to reconnect to Pipeweaver, sending Quit");
+                let _ = tx.send(WindowMessage::Quit);
+                return;
+            }
+        }
+    }
+}
+
+fn initial_connect(uri: &Uri, wait: Option<WaitPolicy>) -> Result<Socket> {
+    let Some(wait) = wait else {
+        return open_socket(uri);
+    };
+
+    let started = Instant::now();
+    loop {
+        match open_socket(uri) {
+            Ok(socket) => return Ok(socket),
+            Err(e) => {
+                if started.elapsed() + wait.interval > wait.timeout {
+                    return Err(e);
+                }
+                debug!(
+                    "Pipeweaver not available yet ({e}), retrying in {:?}",
+                    wait.interval
+                );
+                thread::sleep(wait.interval);
+            }
+        }
+    }
+}
+
+fn open_socket(uri: &Uri) -> Result<Socket> {
+    let (socket, response) = connect(uri)?;
+    info!("Connected, HTTP status: {}", response.status());
+
+    // Reads and writes never block, we wait in poll() for either the daemon or a request instead
+    if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+        stream.set_nonblocking(true)?;
+    }
+    Ok(socket)
+}
+
+/// Reads from the socket until the connection is dropped, sending any requests as they arrive
+/// and keeping a mirror of the daemon's state.
+fn run_connection(
+    socket: &mut Socket,
+    requests: &RequestQueue,
+    tx: &mpsc::Sender<WindowMessage>,
+    events: &mpsc::Sender<DaemonEvent>,
+) {
+    let mut mirror = DaemonMirror::default();
+    let mut pending = PendingRequests::default();
+
+    // Start with the full status, the daemon then sends patches as it changes
+    if let Err(e) = request_status(socket, &mut pending) {
+        error!("Disconnected: unable to request status: {e}");
+        return;
+    }
+
+    loop {
+        for request in requests.drain() {
+            let message = match pending.prepare(request) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Unable to send request: {e}");
+                    continue;
+                }
+            };
+
+            if let Err(e) = socket.send(Message::text(message))
+                && !would_block(&e)
+            {
+                error!("Disconnected: unable to send request: {e}");
+                return;
+            }
+        }
+
+        // Handle everything that's arrived, until the socket has nothing more for us
+        loop {
+            let msg = match socket.read() {
+                Ok(msg) => msg,
+                Err(e) if would_block(&e) => break,
+                Err(tungstenite::Error::ConnectionClosed) => {
+                    error!("Disconnected: connection closed");
+                    return;
+                }
+                Err(tungstenite::Error::Protocol(e)) => {
+                    error!("Disconnected: protocol error: {e}");
+                    return;
+                }
+                Err(e) => {
+                    error!("Disconnected: other error: {e}");
+                    return;
+                }
+            };
+
+            match msg {
+                Message::Text(text) => {
+                    let response = match serde_json::from_str::<WebsocketResponse>(&text) {
+                        Ok(response) => response,
+                        Err(e) => {
+                            debug!("Ignoring unrecognised message from Pipeweaver: {e}");
+                            continue;
+                        }
+                    };
+
+                    let update = match &response.data {
+                        DaemonResponse::Status(status) => mirror.set_status(status.clone()),
+                        DaemonResponse::Patch(patch) => mirror.apply_patch(patch),
+                        _ => Update::Unchanged,
+                    };
+                    pending.resolve(response.id, response.data);
+
+                    match update {
+                        Update::Changed => {
+                            let _ = tx.send(WindowMessage::DaemonState(mirror.state().clone()));
+                            let _ = events.send(DaemonEvent::State(mirror.state().clone()));
+                        }
+                        Update::OutOfSync => {
+                            if let Err(e) = request_status(socket, &mut pending) {
+                                error!("Disconnected: unable to request status: {e}");
+                                return;
+                            }
+                        }
+                        Update::Unchanged => {}
+                    }
+                }
+                Message::Close(_) => {
+                    info!("Server closed the connection");
+                    return;
+                }
+                // Pings are answered by tungstenite, the pong goes out with the next flush
+                _ => {}
+            }
+        }
+
+        // Anything the socket couldn't take straight away is left buffered until it's writable
+        let flushed = match socket.flush() {
+            Ok(()) => true,
+            Err(e) if would_block(&e) => false,
+            Err(e) => {
+                error!("Disconnected: unable to send: {e}");
+                return;
+            }
+        };
+
+        pending.expire();
+        if let Err(e) = wait(socket, requests, pending.next_deadline(), !flushed) {
+            error!("Disconnected: unable to wait on the socket: {e}");
+            return;
+        }
+    }
+}
+
+fn would_block(e: &tungstenite::Error) -> bool {
+    matches!(e, tungstenite::Error::Io(e) if e.kind() == ErrorKind::WouldBlock)
+}
+
+/// Sleeps until the daemon sends something, a request is queued, or the deadline passes
+fn wait(
+    socket: &Socket,
+    requests: &RequestQueue,
+    deadline: Option<Instant>,
+    writing: bool,
+) -> io::Result<()> {
+    let MaybeTlsStream::Plain(stream) = socket.get_ref() else {
+        return Err(io::Error::other("Only plain connections are supported"));
+    };
+
+    let mut events = libc::POLLIN;
+    if writing {
+        events |= libc::POLLOUT;
+    }
+    let mut fds = [
+        libc::pollfd {
+            fd: stream.as_raw_fd(),
+            events,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: requests.wake_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+
+    // Round up, so we don't wake just before the deadline and go straight back to sleep
+    let timeout = match deadline {
+        Some(deadline) => {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            i32::try_from(remaining.as_millis() + 1).unwrap_or(i32::MAX)
+        }
+        None => -1,
+    };
+
+    if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } < 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() != ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+// Nobody waits on this, the response is picked up by the mirror. It's only queued here, the
+// flush at the end of each pass through the connection loop sends it.
+fn request_status(socket: &mut Socket, pending: &mut PendingRequests) -> Result<()> {
+    let request = ClientRequest::new(DaemonRequest::GetStatus);
+    match socket.write(Message::text(pending.prepare(request)?)) {
+        Err(e) if !would_block(&e) => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Attempts to reconnect using the policy's backoff, returns None if we've given up
+fn reconnect(uri: &Uri, policy: &ReconnectPolicy) -> Option<Socket> {
+    let started = Instant::now();
+    let mut attempt = 0;
+
+    loop {
+        let delay = policy.delay_for(attempt);
+        if let Some(give_up) = policy.give_up_after
+            && started.elapsed() + delay > give_up
+        {
+            warn!("Giving up on Pipeweaver after {:?}", started.elapsed());
+            return None;
+        }
+
+        debug!("Reconnect attempt {} in {delay:?}", attempt + 1);
+        thread::sleep(delay);
+
+        match open_socket(uri) {
+            Ok(socket) => return Some(socket),
+            Err(e) => debug!("Reconnect attempt {} failed: {e}", attempt + 1),
+        }
+        attempt = attempt.saturating_add(1);
+    }
+}
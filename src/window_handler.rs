@@ -1,14 +1,64 @@
+use crate::daemon_model::DaemonModel;
+use crate::daemon_state::DaemonState;
+use crate::dbus::DbusService;
+use crate::tray::TrayHandle;
+use cpp::cpp;
+use log::debug;
 use qmetaobject::prelude::*;
-use std::sync::mpsc;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+cpp! {{
+    #include <QtGlobal>
+}}
 
 pub enum WindowMessage {
     Trigger,
-    Close,
+
+    // An xdg-activation token to be used the next time the window is activated
+    ActivationToken(String),
+
+    // Window management requests from IPC
+    Hide,
+    Toggle,
+    Quit,
+    Reload,
+    Navigate(String),
+
+    // Change whether closing the window hides it to the tray
+    CloseToTray(bool),
+
+    // Open the mini mixer window
+    ShowMixer,
+
+    // Connection state to the Pipeweaver daemon
+    Connected,
+    Disconnected,
+    Reconnected,
+
+    // The daemon's state has changed
+    DaemonState(DaemonState),
+}
+
+/// State shared with the background threads, so they can report on it without touching QObjects
+#[derive(Default)]
+pub struct AppStatus {
+    pub connected: AtomicBool,
+    pub visible: AtomicBool,
+    pub daemon: Mutex<DaemonState>,
 }
 
 #[derive(QObject)]
 pub struct WindowHandler {
-    rx: mpsc::Receiver<WindowMessage>,
+    status: Arc<AppStatus>,
+    tray: Option<TrayHandle>,
+    dbus: Option<DbusService>,
+    daemon: Rc<RefCell<DaemonModel>>,
+
+    // An xdg-activation token waiting for the window to next be activated
+    activation_token: RefCell<Option<String>>,
     base: qt_base_class!(trait QObject),
 
     // Called to focus the QT Window
@@ -19,45 +69,241 @@ pub struct WindowHandler {
         }
     ),
 
-    // Called to close the QT Window
-    close: qt_signal!(),
-    on_close: qt_method!(
-        fn on_close(&self) {
-            self.close();
+    // Called to hide the QT Window
+    hide: qt_signal!(),
+    on_hide: qt_method!(
+        fn on_hide(&self) {
+            self.hide();
         }
     ),
 
-    // Called from QT to probe the message queue
-    check_notifications: qt_method!(
-        fn check_notifications(&self) {
-            while let Ok(msg) = self.rx.try_recv() {
-                match msg {
-                    WindowMessage::Trigger => {
-                        self.on_trigger();
-                    }
-                    WindowMessage::Close => {
-                        // Handle close request from IPC
-                        self.on_close();
-                    }
-                }
+    // Called to show the QT Window if hidden, or hide it if shown
+    toggle: qt_signal!(),
+    on_toggle: qt_method!(
+        fn on_toggle(&self) {
+            self.toggle();
+        }
+    ),
+
+    // Called to exit the application
+    quit: qt_signal!(),
+    on_quit: qt_method!(
+        fn on_quit(&self) {
+            self.quit();
+        }
+    ),
+
+    // Called to reload the Web UI
+    reload: qt_signal!(),
+    on_reload: qt_method!(
+        fn on_reload(&self) {
+            self.reload();
+        }
+    ),
+
+    // Called to navigate the Web UI to a path
+    navigate: qt_signal!(path: QString),
+    on_navigate: qt_method!(
+        fn on_navigate(&self, path: QString) {
+            self.navigate(path);
+        }
+    ),
+
+    // Called to change whether closing the window hides it
+    close_to_tray: qt_signal!(enabled: bool),
+    on_close_to_tray: qt_method!(
+        fn on_close_to_tray(&self, enabled: bool) {
+            self.close_to_tray(enabled);
+        }
+    ),
+
+    // Called to open the mini mixer window
+    show_mixer: qt_signal!(),
+    on_show_mixer: qt_method!(
+        fn on_show_mixer(&self) {
+            self.show_mixer();
+        }
+    ),
+
+    // Called when the first connection to the daemon has been established
+    connected: qt_signal!(),
+    on_connected: qt_method!(
+        fn on_connected(&self) {
+            self.connected();
+        }
+    ),
+
+    // Called when the connection to the daemon is lost
+    disconnected: qt_signal!(),
+    on_disconnected: qt_method!(
+        fn on_disconnected(&self) {
+            self.disconnected();
+        }
+    ),
+
+    // Called when the connection to the daemon has been re-established
+    reconnected: qt_signal!(),
+    on_reconnected: qt_method!(
+        fn on_reconnected(&self) {
+            self.reconnected();
+        }
+    ),
+
+    // Called from QT when the window is shown or hidden
+    set_visible: qt_method!(
+        fn set_visible(&self, visible: bool) {
+            self.status.visible.store(visible, Ordering::Relaxed);
+            if let Some(tray) = &self.tray {
+                tray.set_visible(visible);
+            }
+            if let Some(dbus) = &self.dbus {
+                dbus.visible_changed();
             }
         }
     ),
 }
 
 impl WindowHandler {
-    pub fn new(rx: mpsc::Receiver<WindowMessage>) -> Self {
+    pub fn new(
+        status: Arc<AppStatus>,
+        tray: Option<TrayHandle>,
+        dbus: Option<DbusService>,
+        daemon: Rc<RefCell<DaemonModel>>,
+    ) -> Self {
         Self {
-            rx,
+            status,
+            tray,
+            dbus,
+            daemon,
+            activation_token: RefCell::new(None),
             base: Default::default(),
 
             trigger: Default::default(),
             on_trigger: Default::default(),
 
-            close: Default::default(),
-            on_close: Default::default(),
+            hide: Default::default(),
+            on_hide: Default::default(),
+
+            toggle: Default::default(),
+            on_toggle: Default::default(),
+
+            quit: Default::default(),
+            on_quit: Default::default(),
+
+            reload: Default::default(),
+            on_reload: Default::default(),
+
+            navigate: Default::default(),
+            on_navigate: Default::default(),
+
+            close_to_tray: Default::default(),
+            on_close_to_tray: Default::default(),
+
+            show_mixer: Default::default(),
+            on_show_mixer: Default::default(),
+
+            connected: Default::default(),
+            on_connected: Default::default(),
+
+            disconnected: Default::default(),
+            on_disconnected: Default::default(),
+
+            reconnected: Default::default(),
+            on_reconnected: Default::default(),
 
-            check_notifications: Default::default(),
+            set_visible: Default::default(),
+        }
+    }
+
+    /// Called on the Qt thread for each message sent from the background threads
+    pub fn handle_message(&self, msg: WindowMessage) {
+        match msg {
+            WindowMessage::Trigger => {
+                self.apply_activation_token();
+                self.on_trigger();
+            }
+            WindowMessage::ActivationToken(token) => {
+                debug!("Received activation token");
+                self.activation_token.replace(Some(token));
+            }
+            WindowMessage::Hide => {
+                self.on_hide();
+            }
+            WindowMessage::Toggle => {
+                self.apply_activation_token();
+                self.on_toggle();
+            }
+            WindowMessage::Quit => {
+                self.on_quit();
+            }
+            WindowMessage::Reload => {
+                self.on_reload();
+            }
+            WindowMessage::Navigate(path) => {
+                self.on_navigate(path.into());
+            }
+            WindowMessage::CloseToTray(enabled) => {
+                self.on_close_to_tray(enabled);
+            }
+            WindowMessage::ShowMixer => {
+                self.apply_activation_token();
+                self.on_show_mixer();
+            }
+            WindowMessage::Connected => {
+                self.set_connected(true);
+                self.on_connected();
+            }
+            WindowMessage::Disconnected => {
+                self.set_connected(false);
+                self.on_disconnected();
+            }
+            WindowMessage::Reconnected => {
+                self.set_connected(true);
+                self.on_reconnected();
+            }
+            WindowMessage::DaemonState(state) => {
+                // Release the model before notifying, QML will read it back straight away
+                let changed = self.daemon.borrow_mut().set_state(&state);
+                let model = self.daemon.borrow();
+                model.emit_state_changed();
+                for channel in &changed {
+                    model.emit_channel_changed(channel);
+                }
+
+                if let Ok(mut daemon) = self.status.daemon.lock() {
+                    *daemon = state;
+                }
+            }
+        }
+    }
+
+    // Qt's Wayland plugin reads XDG_ACTIVATION_TOKEN on the next requestActivate(), and unsets it
+    // once it's been used, so it's set just before the window is shown.
+    fn apply_activation_token(&self) {
+        let Some(token) = self.activation_token.take() else {
+            return;
+        };
+        let token = QByteArray::from(token.as_str());
+
+        // SAFETY: Other threads are running by now, so this can't go through env::set_var. qputenv
+        // holds Qt's environment lock, which every Qt read of the environment also takes, including
+        // the Wayland plugin's read and qunsetenv of this variable on this same thread. Nothing
+        // outside Qt reads this variable, and writing it is no different to the qunsetenv Qt itself
+        // makes after each activation.
+        unsafe {
+            cpp!([token as "QByteArray"] {
+                qputenv("XDG_ACTIVATION_TOKEN", token);
+            });
+        }
+    }
+
+    fn set_connected(&self, connected: bool) {
+        self.status.connected.store(connected, Ordering::Relaxed);
+        if let Some(tray) = &self.tray {
+            tray.set_connected(connected);
+        }
+        if let Some(dbus) = &self.dbus {
+            dbus.connected_changed();
         }
     }
 }
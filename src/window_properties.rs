@@ -1,15 +1,29 @@
+use crate::config::Config;
+use crate::screens::{self, Rect};
+use crate::settings_file::{Settings, WindowState};
 use log::debug;
 use qmetaobject::prelude::*;
-use serde::{Deserialize, Serialize};
-use std::fs;
-use std::path::PathBuf;
-
-#[derive(Serialize, Deserialize)]
-struct WindowGeometry {
-    width: i32,
-    height: i32,
-    x: i32,
-    y: i32,
+use std::cell::RefCell;
+use std::rc::Rc;
+
+impl WindowState {
+    // These map to QWindow::Visibility, which is what QML gives us
+    fn from_visibility(visibility: i32) -> Option<Self> {
+        match visibility {
+            2 => Some(WindowState::Normal),
+            4 => Some(WindowState::Maximized),
+            5 => Some(WindowState::FullScreen),
+            _ => None,
+        }
+    }
+
+    fn visibility(self) -> i32 {
+        match self {
+            WindowState::Normal => 2,
+            WindowState::Maximized => 4,
+            WindowState::FullScreen => 5,
+        }
+    }
 }
 
 #[derive(Default, QObject)]
@@ -28,80 +42,159 @@ pub struct WindowProperties {
     x_changed: qt_signal!(),
     y_changed: qt_signal!(),
 
+    // The QWindow::Visibility to show the window with, kept up to date by QML
+    visibility: qt_property!(i32; NOTIFY visibility_changed),
+    visibility_changed: qt_signal!(),
+
+    // Whether the window's position can be set and saved, which isn't the case on Wayland
+    can_position: qt_property!(bool; NOTIFY can_position_changed),
+    can_position_changed: qt_signal!(),
+
+    // The base URL of the Pipeweaver UI, resolved from the config
+    daemon_url: qt_property!(QString; NOTIFY daemon_url_changed),
+    daemon_url_changed: qt_signal!(),
+
+    // The page to open the UI at, relative to daemon_url
+    initial_path: qt_property!(QString; NOTIFY initial_path_changed),
+    initial_path_changed: qt_signal!(),
+
+    // Whether the UI should show the 'Waiting for Pipeweaver' screen until we connect
+    wait_for_daemon: qt_property!(bool; NOTIFY wait_for_daemon_changed),
+    wait_for_daemon_changed: qt_signal!(),
+
+    // Whether closing the window hides it rather than exiting the app
+    close_to_tray: qt_property!(bool; NOTIFY close_to_tray_changed),
+    close_to_tray_changed: qt_signal!(),
+
+    // Tracks the window's visibility, so it's saved along with the geometry
+    visible: qt_property!(bool; NOTIFY visible_changed),
+    visible_changed: qt_signal!(),
+
+    // How often (in ms) the web UI is asked to collect garbage, 0 to never
+    gc_interval: qt_property!(i32; NOTIFY gc_interval_changed),
+    gc_interval_changed: qt_signal!(),
+
+    // Whether the window should stay hidden when the app starts
+    start_hidden: qt_property!(bool; NOTIFY start_hidden_changed),
+    start_hidden_changed: qt_signal!(),
+
     // Custom signal for window closing
     close_requested: qt_signal!(),
     handle_close_request: qt_method!(fn(&mut self) -> bool),
+    handle_quit_request: qt_method!(fn(&mut self)),
+
+    // Without a tray icon there'd be no way back to a hidden window, so closing always exits
+    has_tray: bool,
+
+    // The Qt platform plugin in use, the saved geometry is kept separately for each
+    platform: String,
+    settings: Rc<RefCell<Settings>>,
 }
 
 impl WindowProperties {
-    fn get_config_path() -> PathBuf {
-        let mut path = dirs::config_dir().unwrap_or_else(|| PathBuf::from("."));
-        path.push("pipeweaver");
-        fs::create_dir_all(&path).ok();
-        path.push("window.json");
-        path
-    }
+    pub fn new(
+        config: &Config,
+        settings: Rc<RefCell<Settings>>,
+        initial_path: Option<String>,
+        has_tray: bool,
+    ) -> Self {
+        let mut stored = settings.borrow_mut();
 
-    fn load_geometry() -> WindowGeometry {
-        let path = Self::get_config_path();
-        if let Ok(content) = fs::read_to_string(path)
-            && let Ok(geometry) = serde_json::from_str::<WindowGeometry>(&content)
-        {
-            debug!(
-                "Loaded geometry: {}x{} at ({}, {})",
-                geometry.width, geometry.height, geometry.x, geometry.y
-            );
-            return geometry;
-        }
+        let platform = screens::platform_name();
+        let can_position = screens::can_position_windows(&platform);
+        let saved = screens::saved_geometry(&stored.window, &platform);
 
-        // Default values if file doesn't exist or is invalid
-        let geometry = WindowGeometry {
-            width: 1000, // Match minimumWidth from QML
-            height: 600, // Match minimumHeight from QML
-            x: 100,
-            y: 100,
-        };
+        let screens = screens::screens();
+        let placement = screens::layout_placement(&saved, &screens);
         debug!(
-            "Using default geometry: {}x{} at ({}, {})",
-            geometry.width, geometry.height, geometry.x, geometry.y
+            "Loaded {platform} geometry: {}x{} at ({:?}, {:?})",
+            placement.width, placement.height, placement.x, placement.y
         );
-        geometry
-    }
 
-    pub fn new() -> Self {
-        let geometry = Self::load_geometry();
+        let placed = screens::restore(placement, can_position, &screens);
+        let visibility = placement.state.visibility();
+        stored
+            .window
+            .platforms
+            .entry(platform.clone())
+            .or_insert(saved);
+
+        // Only an explicit request starts us hidden, so launching the app always shows a window
+        let close_to_tray = stored.preferences.close_to_tray;
+        let start_hidden = config.start_hidden;
+        if start_hidden {
+            debug!("Starting with the window hidden");
+        }
+        drop(stored);
+
         WindowProperties {
-            width: geometry.width,
-            height: geometry.height,
-            x: geometry.x,
-            y: geometry.y,
+            width: placed.width,
+            height: placed.height,
+            x: placed.x,
+            y: placed.y,
+            visibility,
+            can_position,
+            platform,
+            settings,
+
+            daemon_url: config.daemon.base_url().into(),
+            initial_path: initial_path.unwrap_or_default().into(),
+            wait_for_daemon: config.wait.is_some(),
+            gc_interval: i32::try_from(config.webengine.gc_interval_ms).unwrap_or(i32::MAX),
+
+            close_to_tray,
+            visible: !start_hidden,
+            start_hidden,
+            has_tray,
 
             ..Default::default()
         }
     }
 
     pub fn save_geometry(&self) {
-        let geometry = WindowGeometry {
-            width: self.width,
-            height: self.height,
+        let screens = screens::screens();
+        let window = Rect {
             x: self.x,
             y: self.y,
+            width: self.width,
+            height: self.height,
         };
+        let state = WindowState::from_visibility(self.visibility).unwrap_or_default();
+        let placement = screens::placement(window, state, self.can_position, &screens);
 
         debug!(
-            "Saving geometry: {}x{} at ({}, {})",
-            geometry.width, geometry.height, geometry.x, geometry.y
+            "Saving {} geometry: {}x{} at ({:?}, {:?}), {:?}",
+            self.platform, self.width, self.height, placement.x, placement.y, placement.state
         );
 
-        if let Ok(json) = serde_json::to_string_pretty(&geometry) {
-            let path = Self::get_config_path();
-            fs::write(path, json).ok();
-        }
+        let mut settings = self.settings.borrow_mut();
+        let current = settings
+            .window
+            .platforms
+            .entry(self.platform.clone())
+            .or_default();
+        screens::remember(current, placement, &screens);
+
+        settings.window.visible = self.visible;
+        settings.preferences.close_to_tray = self.close_to_tray;
+        settings.save();
     }
 
+    /// Returns false if the window should be hidden rather than closed
     pub fn handle_close_request(&mut self) -> bool {
         self.save_geometry();
+        if self.close_to_tray && self.has_tray {
+            debug!("Close requested, hiding the window");
+            return false;
+        }
+
         self.close_requested();
         true
     }
+
+    /// Called when the app is exiting, regardless of the close behaviour
+    pub fn handle_quit_request(&mut self) {
+        self.save_geometry();
+        self.close_requested();
+    }
 }